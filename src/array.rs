@@ -0,0 +1,199 @@
+use crate::hl::{Melody, Note, Opl2, Opl2Error};
+use crate::instrument::MelodyInstrument;
+use crate::ll::{ChannelId, HardwareInterface};
+use core::cmp::Reverse;
+use embedded_hal::blocking::delay::DelayUs;
+
+#[derive(Debug)]
+pub enum ArrayError {
+    InvalidChannel,
+    InvalidBoard,
+    /// The board has failed before and is left alone
+    BoardFailed(usize),
+    /// The board gave an error on this call and has been marked as failed
+    Opl2Error(usize, Opl2Error),
+}
+
+/// Drives multiple boards as one big chip.
+///
+/// The channels of the boards are put after each other, so channel 0-8 are on board 0, 9-17 on board 1 and so on.
+/// A board that gives an error is marked as failed. Everything on its channels returns [ArrayError::BoardFailed]
+/// from then on, while the other boards keep playing.
+pub struct OplArray<I: HardwareInterface, const N: usize> {
+    boards: [Opl2<I, Melody>; N],
+    failed: [bool; N],
+    latency_offsets: [u16; N],
+}
+
+impl<I: HardwareInterface, const N: usize> OplArray<I, N> {
+    /// The amount of channels of all boards together
+    pub const CHANNEL_COUNT: usize = ChannelId::COUNT * N;
+
+    /// Creates an array out of initialized boards
+    pub fn new(boards: [Opl2<I, Melody>; N]) -> Self {
+        Self {
+            boards,
+            failed: [false; N],
+            latency_offsets: [0; N],
+        }
+    }
+
+    pub fn free(self) -> [Opl2<I, Melody>; N] {
+        self.boards
+    }
+
+    /// Direct access to a board, for example to set up its rhythm section
+    pub fn board(&mut self, board: usize) -> Option<&mut Opl2<I, Melody>> {
+        self.boards.get_mut(board)
+    }
+
+    /// Returns true if the board has been marked as failed
+    pub fn is_failed(&self, board: usize) -> bool {
+        self.failed.get(board).copied().unwrap_or(false)
+    }
+
+    /// Marks the board as failed, so it's left alone until it's rejoined
+    pub fn mark_failed(&mut self, board: usize) {
+        if let Some(failed) = self.failed.get_mut(board) {
+            *failed = true;
+        }
+    }
+
+    /// Re-initializes the board and takes it back into use.
+    ///
+    /// The other boards are not touched, so they keep playing.
+    /// The board comes back with all registers cleared, so its instruments have to be set up again.
+    /// If the initialization fails, the board stays marked as failed.
+    pub fn rejoin(&mut self, board: usize) -> Result<(), ArrayError> {
+        let opl = self.boards.get_mut(board).ok_or(ArrayError::InvalidBoard)?;
+
+        opl.reinitialize()
+            .map_err(|e| ArrayError::Opl2Error(board, e))?;
+        self.failed[board] = false;
+
+        Ok(())
+    }
+
+    /// Sets how many µs later the writes to the board take effect than those to a board without latency.
+    ///
+    /// This is used by [Self::start_channels] to make notes on different boards sound at the same time.
+    /// Any time it takes to send the writes themselves can be included in the offset too.
+    pub fn set_latency_offset(&mut self, board: usize, offset_us: u16) {
+        if let Some(offset) = self.latency_offsets.get_mut(board) {
+            *offset = offset_us;
+        }
+    }
+
+    /// Like [Self::set_latency_offset], but with a typed duration.
+    ///
+    /// Offsets above `u16::MAX` µs are clamped.
+    #[cfg(feature = "fugit")]
+    pub fn set_latency(&mut self, board: usize, offset: fugit::MicrosDurationU32) {
+        let offset_us = offset.to_micros().min(u16::MAX as u32) as u16;
+        self.set_latency_offset(board, offset_us);
+    }
+
+    /// Starts the notes so they sound at the same time, even when they are on different boards.
+    ///
+    /// The boards are written in order from the highest latency offset to the lowest,
+    /// with a delay of the difference between them.
+    /// A failing board doesn't stop the notes on the other boards. The first error is returned.
+    pub fn start_channels<D: DelayUs<u16>>(
+        &mut self,
+        notes: &[(usize, Note)],
+        delay: &mut D,
+    ) -> Result<(), ArrayError> {
+        let mut order = [0; N];
+        for (i, board) in order.iter_mut().enumerate() {
+            *board = i;
+        }
+        order.sort_unstable_by_key(|board| Reverse(self.latency_offsets[*board]));
+
+        let mut result = Ok(());
+        let mut previous_offset = None;
+
+        for board in order.iter() {
+            let board_notes = notes
+                .iter()
+                .filter(|(channel, _)| channel / ChannelId::COUNT == *board);
+
+            if board_notes.clone().next().is_none() {
+                continue;
+            }
+
+            let offset = self.latency_offsets[*board];
+            if let Some(previous_offset) = previous_offset {
+                delay.delay_us(previous_offset - offset);
+            }
+            previous_offset = Some(offset);
+
+            for (channel, note) in board_notes {
+                let note_result = self.start_channel(*channel, *note);
+                if result.is_ok() {
+                    result = note_result;
+                }
+            }
+        }
+
+        // The notes that are on no board at all
+        if result.is_ok()
+            && notes
+                .iter()
+                .any(|(channel, _)| *channel >= Self::CHANNEL_COUNT)
+        {
+            result = Err(ArrayError::InvalidChannel);
+        }
+
+        result
+    }
+
+    pub fn setup_melody_instrument(
+        &mut self,
+        channel: usize,
+        value: MelodyInstrument,
+    ) -> Result<(), ArrayError> {
+        self.on_board(channel, |opl, channel| {
+            opl.setup_melody_instrument(channel, value)
+        })
+    }
+
+    pub fn play_note(
+        &mut self,
+        channel: usize,
+        instrument: &MelodyInstrument,
+        note: Note,
+    ) -> Result<(), ArrayError> {
+        self.on_board(channel, |opl, channel| {
+            opl.play_note(channel, instrument, note)
+        })
+    }
+
+    pub fn start_channel(&mut self, channel: usize, note: Note) -> Result<(), ArrayError> {
+        self.on_board(channel, |opl, channel| opl.start_channel(channel, note))
+    }
+
+    pub fn stop_channel(&mut self, channel: usize) -> Result<(), ArrayError> {
+        self.on_board(channel, |opl, channel| opl.stop_channel(channel))
+    }
+
+    /// Runs the operation on the board of the flat channel with the channel number of that board
+    fn on_board(
+        &mut self,
+        channel: usize,
+        f: impl FnOnce(&mut Opl2<I, Melody>, usize) -> Result<(), Opl2Error>,
+    ) -> Result<(), ArrayError> {
+        if channel >= Self::CHANNEL_COUNT {
+            return Err(ArrayError::InvalidChannel);
+        }
+
+        let board = channel / ChannelId::COUNT;
+        if self.failed[board] {
+            return Err(ArrayError::BoardFailed(board));
+        }
+
+        f(&mut self.boards[board], channel % ChannelId::COUNT).map_err(|e| {
+            self.failed[board] = true;
+            ArrayError::Opl2Error(board, e)
+        })
+    }
+}
@@ -0,0 +1,216 @@
+//! An async driver for executors like embassy.
+//!
+//! The register writes need delays of tens of µs, which the blocking driver busy-waits.
+//! [AsyncOpl2] runs the blocking driver on a register image in memory instead and then sends the
+//! writes it made to the [AsyncHardwareInterface], where the delays can be awaited.
+
+#[cfg(feature = "rhythm")]
+use crate::hl::Rhythm;
+use crate::hl::{
+    InitConfig, Initialized, Melody, Note, Opl2, Opl2Error, Uninitialized, REFRESHED_REGISTER_COUNT,
+};
+use crate::instrument::MelodyInstrument;
+use crate::ll::{
+    AsyncHardwareInterface, InterfaceError, MemoryInterface, RecordingInterface, WriteRecorder,
+};
+
+/// The maximum amount of register writes a single operation of [AsyncOpl2::apply] can do.
+///
+/// Enough for the register clearing of the reset and [Opl2::safe_silence], which are the biggest operations of the driver.
+pub const MAX_OPERATION_WRITES: usize = 128;
+
+/// The register image of the blocking driver in [AsyncOpl2], which records all writes of an operation
+pub type ModelInterface = RecordingInterface<MemoryInterface, OperationWrites>;
+
+/// The writes of an operation, so they can be replayed to the chip afterwards
+pub struct OperationWrites {
+    writes: [(u8, u8); MAX_OPERATION_WRITES],
+    write_count: usize,
+    /// Writes reached the image that didn't fit in the recording
+    overflowed: bool,
+}
+
+impl OperationWrites {
+    const fn new() -> Self {
+        Self {
+            writes: [(0, 0); MAX_OPERATION_WRITES],
+            write_count: 0,
+            overflowed: false,
+        }
+    }
+
+    /// The writes since the last [Self::clear], in order
+    fn recorded(&self) -> &[(u8, u8)] {
+        &self.writes[..self.write_count]
+    }
+
+    fn clear(&mut self) {
+        self.write_count = 0;
+        self.overflowed = false;
+    }
+}
+
+impl WriteRecorder for OperationWrites {
+    fn record(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        let slot = match self.writes.get_mut(self.write_count) {
+            Some(slot) => slot,
+            None => {
+                self.overflowed = true;
+                return Err(InterfaceError::TooManyWrites);
+            }
+        };
+        *slot = (address, value);
+        self.write_count += 1;
+        Ok(())
+    }
+}
+
+/// The async version of [Opl2]
+pub struct AsyncOpl2<I: AsyncHardwareInterface, STATE> {
+    interface: I,
+    /// The blocking driver, which does all the work on the register image
+    model: Opl2<ModelInterface, STATE>,
+    /// The chip may be different from the image, because writes have been lost
+    out_of_sync: bool,
+}
+
+impl<I: AsyncHardwareInterface> AsyncOpl2<I, Uninitialized> {
+    pub fn new(interface: I) -> Self {
+        Self {
+            interface,
+            model: Opl2::new(ModelInterface::with_recorder(
+                MemoryInterface::new(),
+                OperationWrites::new(),
+            )),
+            out_of_sync: false,
+        }
+    }
+
+    /// Resets the chip and clears all registers
+    pub async fn initialize(self) -> Result<AsyncOpl2<I, Melody>, Opl2Error> {
+        self.initialize_with(InitConfig::default()).await
+    }
+
+    /// Resets the chip with the given config
+    pub async fn initialize_with(
+        mut self,
+        config: InitConfig,
+    ) -> Result<AsyncOpl2<I, Melody>, Opl2Error> {
+        self.interface.reset().await?;
+
+        // The reset of the model only clears its image, the register clearing is recorded and sent afterwards
+        let model = self.model.initialize_with(config)?;
+
+        let mut opl = AsyncOpl2 {
+            interface: self.interface,
+            model,
+            out_of_sync: false,
+        };
+        opl.flush().await?;
+        Ok(opl)
+    }
+}
+
+impl<I: AsyncHardwareInterface, STATE> AsyncOpl2<I, STATE> {
+    /// Returns true if writes to the chip have been lost, so it may be different from the image of the driver.
+    ///
+    /// That happens when sending fails partway or an operation does more than [MAX_OPERATION_WRITES] writes.
+    /// [AsyncOpl2::resync] sends the image again.
+    pub fn is_out_of_sync(&self) -> bool {
+        self.out_of_sync
+    }
+
+    /// Sends the recorded writes to the chip
+    async fn flush(&mut self) -> Result<(), Opl2Error> {
+        let recording = self.model.interface_mut().recorder_mut();
+        let result = self.interface.write_batch(recording.recorded()).await;
+        // The image already has all the writes, so a failure leaves the chip behind
+        self.out_of_sync |= recording.overflowed || result.is_err();
+        recording.clear();
+        Ok(result?)
+    }
+}
+
+impl<I: AsyncHardwareInterface, INIT: Initialized> AsyncOpl2<I, INIT> {
+    /// Runs the operation of the blocking driver and sends its register writes to the chip.
+    ///
+    /// This gives async access to everything the blocking driver can do, as long as the operation
+    /// doesn't do more than [MAX_OPERATION_WRITES] writes.
+    /// The writes that were made before an operation failed are still sent, so the chip keeps matching the image.
+    /// If the writes can't all be sent, the driver is out of sync, see [Self::is_out_of_sync].
+    pub async fn apply<R>(
+        &mut self,
+        operation: impl FnOnce(&mut Opl2<ModelInterface, INIT>) -> Result<R, Opl2Error>,
+    ) -> Result<R, Opl2Error> {
+        let result = operation(&mut self.model);
+        self.flush().await?;
+        result
+    }
+
+    /// Sends every register of the image to the chip again, so it is in sync after writes have been lost
+    pub async fn resync(&mut self) -> Result<(), Opl2Error> {
+        let mut remaining = REFRESHED_REGISTER_COUNT;
+        while remaining > 0 {
+            let count = remaining.min(MAX_OPERATION_WRITES);
+            self.model.refresh(count)?;
+            self.flush().await?;
+            remaining -= count;
+        }
+
+        self.out_of_sync = false;
+        Ok(())
+    }
+
+    pub async fn setup_melody_instrument(
+        &mut self,
+        channel: usize,
+        instrument: MelodyInstrument,
+    ) -> Result<(), Opl2Error> {
+        self.apply(|opl| opl.setup_melody_instrument(channel, instrument))
+            .await
+    }
+
+    pub async fn play_note(
+        &mut self,
+        channel: usize,
+        instrument: &MelodyInstrument,
+        note: Note,
+    ) -> Result<(), Opl2Error> {
+        self.apply(|opl| opl.play_note(channel, instrument, note))
+            .await
+    }
+
+    pub async fn start_channel(&mut self, channel: usize, note: Note) -> Result<(), Opl2Error> {
+        self.apply(|opl| opl.start_channel(channel, note)).await
+    }
+
+    pub async fn stop_channel(&mut self, channel: usize) -> Result<(), Opl2Error> {
+        self.apply(|opl| opl.stop_channel(channel)).await
+    }
+}
+
+#[cfg(feature = "rhythm")]
+impl<I: AsyncHardwareInterface> AsyncOpl2<I, Melody> {
+    pub async fn into_rhythm_mode(self) -> Result<AsyncOpl2<I, Rhythm>, Opl2Error> {
+        let mut opl = AsyncOpl2 {
+            interface: self.interface,
+            model: self.model.into_rhythm_mode()?,
+            out_of_sync: self.out_of_sync,
+        };
+        opl.flush().await?;
+        Ok(opl)
+    }
+}
+
+#[cfg(feature = "rhythm")]
+impl<I: AsyncHardwareInterface> AsyncOpl2<I, Rhythm> {
+    pub async fn into_melody_mode(self) -> Result<AsyncOpl2<I, Melody>, Opl2Error> {
+        let mut opl = AsyncOpl2 {
+            interface: self.interface,
+            model: self.model.into_melody_mode()?,
+            out_of_sync: self.out_of_sync,
+        };
+        opl.flush().await?;
+        Ok(opl)
+    }
+}
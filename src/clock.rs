@@ -0,0 +1,99 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+/// A monotonic time source
+pub trait Clock {
+    /// The microseconds since some fixed point in the past. Must never go backwards.
+    fn now_us(&mut self) -> u64;
+
+    /// The current time as typed instant
+    #[cfg(feature = "fugit")]
+    fn now(&mut self) -> fugit::TimerInstantU64<1_000_000> {
+        fugit::TimerInstantU64::from_ticks(self.now_us())
+    }
+
+    /// Busy-waits until the clock has reached the instant
+    #[cfg(feature = "fugit")]
+    fn wait_until(&mut self, deadline: fugit::TimerInstantU64<1_000_000>) {
+        self.wait_until_us(deadline.ticks());
+    }
+
+    /// Busy-waits until the clock has reached the time
+    fn wait_until_us(&mut self, deadline_us: u64) {
+        while self.now_us() < deadline_us {}
+    }
+}
+
+/// Uses a [Clock] as delay provider, so it can be given to everything that takes a delay
+pub struct ClockDelay<C: Clock> {
+    clock: C,
+}
+
+impl<C: Clock> ClockDelay<C> {
+    pub fn new(clock: C) -> Self {
+        Self { clock }
+    }
+
+    pub fn free(self) -> C {
+        self.clock
+    }
+}
+
+impl<C: Clock> DelayUs<u16> for ClockDelay<C> {
+    fn delay_us(&mut self, us: u16) {
+        let deadline = self.clock.now_us() + us as u64;
+        self.clock.wait_until_us(deadline);
+    }
+}
+
+impl<C: Clock> DelayUs<u8> for ClockDelay<C> {
+    fn delay_us(&mut self, us: u8) {
+        DelayUs::<u16>::delay_us(self, us as u16);
+    }
+}
+
+impl<C: Clock> DelayMs<u16> for ClockDelay<C> {
+    fn delay_ms(&mut self, ms: u16) {
+        let deadline = self.clock.now_us() + ms as u64 * 1000;
+        self.clock.wait_until_us(deadline);
+    }
+}
+
+impl<C: Clock> DelayMs<u8> for ClockDelay<C> {
+    fn delay_ms(&mut self, ms: u8) {
+        DelayMs::<u16>::delay_ms(self, ms as u16);
+    }
+}
+
+/// A [Clock] that reads the time from a function that gives a `fugit` instant, like the `now` of most HALs
+#[cfg(feature = "fugit")]
+pub struct FugitClock<F: FnMut() -> fugit::TimerInstantU64<TIMER_HZ>, const TIMER_HZ: u32> {
+    now: F,
+}
+
+#[cfg(feature = "fugit")]
+impl<F: FnMut() -> fugit::TimerInstantU64<TIMER_HZ>, const TIMER_HZ: u32> FugitClock<F, TIMER_HZ> {
+    pub fn new(now: F) -> Self {
+        Self { now }
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<F: FnMut() -> fugit::TimerInstantU64<TIMER_HZ>, const TIMER_HZ: u32> Clock
+    for FugitClock<F, TIMER_HZ>
+{
+    fn now_us(&mut self) -> u64 {
+        (self.now)().duration_since_epoch().to_micros()
+    }
+}
+
+/// A [Clock] on top of the `embassy-time` time driver
+#[cfg(feature = "embassy-time")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EmbassyClock;
+
+#[cfg(feature = "embassy-time")]
+impl Clock for EmbassyClock {
+    fn now_us(&mut self) -> u64 {
+        embassy_time::Instant::now().as_micros()
+    }
+}
@@ -0,0 +1,386 @@
+//! A container that bundles instrument banks, drum kits and songs into one file, like a `content.bin` on external flash.
+//!
+//! Layout, all numbers little endian:
+//!
+//! | Bytes | Content |
+//! |---|---|
+//! | 8 | The header: `OPLC`, the format version (1), a reserved byte and the amount of entries as `u16` |
+//! | 24 per entry | The table of contents: kind, name, offset and length of every entry |
+//! | rest | The payloads |
+//!
+//! A table entry is the kind as `u8`, the name as 15 bytes of UTF-8 padded with zeros,
+//! the offset of the payload from the start of the pack as `u32` and the length of the payload as `u32`.
+//!
+//! The instruments in the payloads are in the serde format of the `serde` feature, encoded with postcard.
+//! That is the format version byte followed by the raw register bytes, so the reader doesn't need postcard.
+//! A bank is a list of [MelodyInstrument]s and a drum kit is a single [DrumKit].
+//! The driver doesn't play songs yet, so their payloads are only available as raw bytes.
+
+#[cfg(feature = "rhythm")]
+use crate::instrument::DrumKit;
+use crate::instrument::MelodyInstrument;
+
+const MAGIC: &[u8; 4] = b"OPLC";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_SIZE: u32 = 8;
+const ENTRY_SIZE: u32 = 24;
+
+/// The version byte that precedes the instruments
+const INSTRUMENT_VERSION: u8 = 1;
+/// The size of an instrument in a bank, including its version byte
+const BANK_INSTRUMENT_SIZE: u32 = 12;
+/// The size of a drum kit payload, including its version byte
+#[cfg(feature = "rhythm")]
+const DRUM_KIT_SIZE: u32 = 32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EntryKind {
+    Bank,
+    DrumKit,
+    Song,
+    /// A kind of a newer version of the format
+    Unknown(u8),
+}
+
+impl From<u8> for EntryKind {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => EntryKind::Bank,
+            2 => EntryKind::DrumKit,
+            3 => EntryKind::Song,
+            value => EntryKind::Unknown(value),
+        }
+    }
+}
+
+/// An entry of the table of contents
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    name: [u8; 15],
+    /// The offset of the payload from the start of the pack
+    pub offset: u32,
+    /// The length of the payload in bytes
+    pub length: u32,
+}
+
+impl Entry {
+    /// The name of the entry, or an empty string if it isn't valid UTF-8
+    pub fn name(&self) -> &str {
+        let length = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..length]).unwrap_or("")
+    }
+}
+
+#[derive(Debug)]
+pub enum ContentError<E> {
+    /// The storage doesn't start with a content pack
+    InvalidHeader,
+    /// The pack has been made for a newer version of the crate
+    UnsupportedVersion(u8),
+    InvalidIndex,
+    /// The entry doesn't have the kind the operation needs
+    WrongKind,
+    /// The payload is damaged or has an unknown format version
+    InvalidPayload,
+    StorageError(E),
+}
+
+/// Reads a content pack from an `embedded-storage` storage.
+///
+/// Nothing is cached, every call reads what it needs from the storage.
+pub struct ContentPack<S: embedded_storage::ReadStorage> {
+    storage: S,
+    offset: u32,
+    entry_count: u16,
+}
+
+impl<S: embedded_storage::ReadStorage> ContentPack<S> {
+    /// Opens the pack that starts at the offset of the storage
+    pub fn open(mut storage: S, offset: u32) -> Result<Self, ContentError<S::Error>> {
+        let mut header = [0; HEADER_SIZE as usize];
+        storage
+            .read(offset, &mut header)
+            .map_err(ContentError::StorageError)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(ContentError::InvalidHeader);
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(ContentError::UnsupportedVersion(header[4]));
+        }
+
+        Ok(Self {
+            storage,
+            offset,
+            entry_count: u16::from_le_bytes([header[6], header[7]]),
+        })
+    }
+
+    pub fn free(self) -> S {
+        self.storage
+    }
+
+    /// The amount of entries in the pack
+    pub fn len(&self) -> usize {
+        self.entry_count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// Reads the table entry at the index
+    pub fn entry(&mut self, index: usize) -> Result<Entry, ContentError<S::Error>> {
+        if index >= self.len() {
+            return Err(ContentError::InvalidIndex);
+        }
+
+        let mut bytes = [0; ENTRY_SIZE as usize];
+        self.read(HEADER_SIZE + index as u32 * ENTRY_SIZE, &mut bytes)?;
+
+        let mut name = [0; 15];
+        name.copy_from_slice(&bytes[1..16]);
+
+        Ok(Entry {
+            kind: bytes[0].into(),
+            name,
+            offset: u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
+            length: u32::from_le_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]),
+        })
+    }
+
+    /// Finds the first entry of the kind with the name
+    pub fn find(
+        &mut self,
+        kind: EntryKind,
+        name: &str,
+    ) -> Result<Option<Entry>, ContentError<S::Error>> {
+        for index in 0..self.len() {
+            let entry = self.entry(index)?;
+            if entry.kind == kind && entry.name() == name {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads a part of the payload of the entry, starting at the position in the payload.
+    ///
+    /// Returns the amount of bytes read, which is less than the buffer when the payload ends.
+    pub fn read_payload(
+        &mut self,
+        entry: &Entry,
+        position: u32,
+        buffer: &mut [u8],
+    ) -> Result<usize, ContentError<S::Error>> {
+        let length = entry
+            .length
+            .saturating_sub(position)
+            .min(buffer.len() as u32) as usize;
+        self.read(payload_position(entry, position)?, &mut buffer[..length])?;
+        Ok(length)
+    }
+
+    /// The amount of instruments in the bank
+    pub fn bank_len(&self, entry: &Entry) -> Result<usize, ContentError<S::Error>> {
+        if entry.kind != EntryKind::Bank {
+            return Err(ContentError::WrongKind);
+        }
+
+        Ok((entry.length / BANK_INSTRUMENT_SIZE) as usize)
+    }
+
+    /// Loads the instrument at the index of the bank
+    pub fn bank_instrument(
+        &mut self,
+        entry: &Entry,
+        index: usize,
+    ) -> Result<MelodyInstrument, ContentError<S::Error>> {
+        if index >= self.bank_len(entry)? {
+            return Err(ContentError::InvalidIndex);
+        }
+
+        let mut bytes = [0; BANK_INSTRUMENT_SIZE as usize];
+        self.read(
+            payload_position(entry, index as u32 * BANK_INSTRUMENT_SIZE)?,
+            &mut bytes,
+        )?;
+        if bytes[0] != INSTRUMENT_VERSION {
+            return Err(ContentError::InvalidPayload);
+        }
+
+        let mut instrument = [0; 11];
+        instrument.copy_from_slice(&bytes[1..]);
+        Ok(MelodyInstrument::from_bytes(instrument))
+    }
+
+    /// Loads the drum kit
+    #[cfg(feature = "rhythm")]
+    pub fn drum_kit(&mut self, entry: &Entry) -> Result<DrumKit, ContentError<S::Error>> {
+        if entry.kind != EntryKind::DrumKit {
+            return Err(ContentError::WrongKind);
+        }
+        if entry.length != DRUM_KIT_SIZE {
+            return Err(ContentError::InvalidPayload);
+        }
+
+        let mut bytes = [0; DRUM_KIT_SIZE as usize];
+        self.read(entry.offset, &mut bytes)?;
+        if bytes[0] != INSTRUMENT_VERSION {
+            return Err(ContentError::InvalidPayload);
+        }
+
+        let mut kit = [0; 31];
+        kit.copy_from_slice(&bytes[1..]);
+        Ok(DrumKit::from_bytes(kit))
+    }
+
+    /// Reads from the storage relative to the start of the pack
+    fn read(&mut self, position: u32, buffer: &mut [u8]) -> Result<(), ContentError<S::Error>> {
+        let address = self
+            .offset
+            .checked_add(position)
+            .ok_or(ContentError::InvalidPayload)?;
+        self.storage
+            .read(address, buffer)
+            .map_err(ContentError::StorageError)
+    }
+}
+
+/// The position in the pack of a position in the payload of the entry.
+///
+/// A damaged table entry can point past the end of the address space, which is an invalid payload.
+fn payload_position<E>(entry: &Entry, position: u32) -> Result<u32, ContentError<E>> {
+    entry
+        .offset
+        .checked_add(position)
+        .ok_or(ContentError::InvalidPayload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A read-only storage on a byte array
+    struct Memory<'a>(&'a [u8]);
+
+    impl embedded_storage::ReadStorage for Memory<'_> {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let start = offset as usize;
+            let source = self.0.get(start..start + bytes.len()).ok_or(())?;
+            bytes.copy_from_slice(source);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    const BANK: [[u8; 11]; 2] = [[0x11; 11], [0x22; 11]];
+    const SONG: &[u8] = b"a song";
+    /// Where the pack starts in the storage
+    const PACK_OFFSET: usize = 4;
+    const PAYLOAD_OFFSET: usize = (HEADER_SIZE + 2 * ENTRY_SIZE) as usize;
+    const SONG_OFFSET: usize = PAYLOAD_OFFSET + 2 * BANK_INSTRUMENT_SIZE as usize;
+
+    fn write_entry(
+        pack: &mut [u8],
+        index: usize,
+        kind: u8,
+        name: &[u8],
+        offset: usize,
+        length: usize,
+    ) {
+        let start = HEADER_SIZE as usize + index * ENTRY_SIZE as usize;
+        let entry = &mut pack[start..start + ENTRY_SIZE as usize];
+        entry[0] = kind;
+        entry[1..1 + name.len()].copy_from_slice(name);
+        entry[16..20].copy_from_slice(&(offset as u32).to_le_bytes());
+        entry[20..24].copy_from_slice(&(length as u32).to_le_bytes());
+    }
+
+    fn storage_bytes() -> [u8; PACK_OFFSET + SONG_OFFSET + 6] {
+        let mut bytes = [0; PACK_OFFSET + SONG_OFFSET + 6];
+        let pack = &mut bytes[PACK_OFFSET..];
+        pack[..8].copy_from_slice(&[b'O', b'P', b'L', b'C', FORMAT_VERSION, 0, 2, 0]);
+        write_entry(
+            pack,
+            0,
+            1,
+            b"pianos",
+            PAYLOAD_OFFSET,
+            2 * BANK_INSTRUMENT_SIZE as usize,
+        );
+        write_entry(pack, 1, 3, b"theme", SONG_OFFSET, SONG.len());
+
+        for (index, instrument) in BANK.iter().enumerate() {
+            let start = PAYLOAD_OFFSET + index * BANK_INSTRUMENT_SIZE as usize;
+            pack[start] = INSTRUMENT_VERSION;
+            pack[start + 1..start + BANK_INSTRUMENT_SIZE as usize].copy_from_slice(instrument);
+        }
+        pack[SONG_OFFSET..].copy_from_slice(SONG);
+        bytes
+    }
+
+    #[test]
+    fn pack_round_trips() {
+        let bytes = storage_bytes();
+        let mut pack = ContentPack::open(Memory(&bytes), PACK_OFFSET as u32).unwrap();
+        assert_eq!(pack.len(), 2);
+
+        let bank = pack.find(EntryKind::Bank, "pianos").unwrap().unwrap();
+        assert_eq!(pack.bank_len(&bank).unwrap(), 2);
+        for (index, instrument) in BANK.iter().enumerate() {
+            assert_eq!(
+                pack.bank_instrument(&bank, index).unwrap().to_bytes(),
+                *instrument
+            );
+        }
+        assert!(matches!(
+            pack.bank_instrument(&bank, 2),
+            Err(ContentError::InvalidIndex)
+        ));
+
+        let song = pack.entry(1).unwrap();
+        assert_eq!((song.kind, song.name()), (EntryKind::Song, "theme"));
+        let mut buffer = [0; 16];
+        let length = pack.read_payload(&song, 2, &mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &SONG[2..]);
+        assert!(matches!(pack.bank_len(&song), Err(ContentError::WrongKind)));
+
+        assert!(pack.find(EntryKind::Song, "pianos").unwrap().is_none());
+        assert!(matches!(pack.entry(2), Err(ContentError::InvalidIndex)));
+    }
+
+    #[test]
+    fn damaged_packs_are_rejected() {
+        let mut bytes = storage_bytes();
+        bytes[PACK_OFFSET + 4] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            ContentPack::open(Memory(&bytes), PACK_OFFSET as u32),
+            Err(ContentError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            ContentPack::open(Memory(&bytes), 0),
+            Err(ContentError::InvalidHeader)
+        ));
+
+        let bytes = storage_bytes();
+        let mut pack = ContentPack::open(Memory(&bytes), PACK_OFFSET as u32).unwrap();
+        let mut song = pack.entry(1).unwrap();
+        song.offset = u32::MAX - 1;
+        assert!(matches!(
+            pack.read_payload(&song, 4, &mut [0; 4]),
+            Err(ContentError::InvalidPayload)
+        ));
+    }
+}
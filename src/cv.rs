@@ -0,0 +1,80 @@
+use crate::hl::{Initialized, Note, Opl2, Opl2Error};
+use crate::ll::HardwareInterface;
+use crate::tuning::{ChipClock, Pitch};
+
+/// Plays a mono voice from a 1V/oct control voltage and a gate, like a Eurorack oscillator.
+///
+/// Feed it every ADC reading with [Self::update]. A rising gate starts the note, a falling gate stops it
+/// and while the gate is high the pitch follows the voltage.
+/// The registers are only written when something changed, so it can be called as often as the ADC samples.
+pub struct CvVoice {
+    channel: usize,
+    zero_volt_pitch: Pitch,
+    quantize: bool,
+    gate: bool,
+    /// The frequency number and block that are playing right now
+    playing: Option<(u16, u8)>,
+}
+
+impl CvVoice {
+    /// Creates a voice on the channel where 0 V is C4, like most modular gear
+    pub fn new(channel: usize) -> Self {
+        Self {
+            channel,
+            zero_volt_pitch: Pitch::of_note(Note::C(4)),
+            quantize: false,
+            gate: false,
+            playing: None,
+        }
+    }
+
+    /// Sets the pitch that is played at 0 V
+    pub fn set_zero_volt_pitch(&mut self, pitch: Pitch) {
+        self.zero_volt_pitch = pitch;
+    }
+
+    /// When on, the pitch snaps to the nearest note instead of gliding along with the voltage
+    pub fn set_quantize(&mut self, quantize: bool) {
+        self.quantize = quantize;
+    }
+
+    /// The pitch that belongs to the voltage, without quantizing
+    pub fn pitch_for(&self, millivolts: i32) -> Pitch {
+        // 1 V per octave is 1.2 cents per mV
+        self.zero_volt_pitch.transposed(millivolts as i64 * 12 / 10)
+    }
+
+    /// Processes a new reading of the control voltage and the gate
+    pub fn update<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        millivolts: i32,
+        gate: bool,
+        clock: &ChipClock,
+    ) -> Result<(), Opl2Error> {
+        let gate_changed = gate != self.gate;
+        self.gate = gate;
+
+        if !gate {
+            if gate_changed {
+                self.playing = None;
+                opl.stop_channel(self.channel)?;
+            }
+            return Ok(());
+        }
+
+        let mut pitch = self.pitch_for(millivolts);
+        if self.quantize {
+            pitch = pitch.snap().unwrap_or(pitch);
+        }
+
+        let registers = clock.saturating_frequency_number(pitch.millihertz());
+
+        if gate_changed || self.playing != Some(registers) {
+            self.playing = Some(registers);
+            opl.start_channel_frequency(self.channel, registers.0, registers.1)?;
+        }
+
+        Ok(())
+    }
+}
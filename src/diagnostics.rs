@@ -0,0 +1,116 @@
+#[cfg(feature = "rhythm")]
+use crate::hl::Rhythm;
+use crate::hl::{Melody, Note, Opl2, Opl2Error};
+use crate::instrument::presets;
+use crate::ll::{HardwareInterface, WaveformType};
+use device_driver::Bit;
+use embedded_hal::blocking::delay::DelayMs;
+
+/// The tone that [exercise] is playing
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExerciseStep {
+    /// A4 on the channel
+    Channel(usize),
+    /// A4 on channel 0 with both operators on the waveform
+    Waveform(WaveformType),
+    #[cfg(feature = "rhythm")]
+    BassDrum,
+    #[cfg(feature = "rhythm")]
+    SnareDrum,
+    #[cfg(feature = "rhythm")]
+    TomTom,
+    #[cfg(feature = "rhythm")]
+    Cymbal,
+    #[cfg(feature = "rhythm")]
+    HiHat,
+}
+
+/// Plays a known tone on every channel, with every waveform and on every drum, one after the other.
+///
+/// Meant for people that have just assembled a board: when all tones sound, the solder joints and
+/// the shift register wiring are fine. `on_step` is called right before every tone, so it can be shown
+/// which tone should be heard. Every tone lasts 200 ms.
+///
+/// The instruments of all channels are left changed, but the chip is back in melody mode.
+/// Without the `rhythm` feature the drums are skipped.
+pub fn exercise<I: HardwareInterface, D: DelayMs<u8>>(
+    mut opl: Opl2<I, Melody>,
+    delay: &mut D,
+    mut on_step: impl FnMut(ExerciseStep),
+) -> Result<Opl2<I, Melody>, Opl2Error> {
+    let mut tone = |opl: &mut Opl2<I, Melody>, channel, step| -> Result<(), Opl2Error> {
+        on_step(step);
+        opl.start_channel(channel, Note::A(4))?;
+        delay.delay_ms(200);
+        opl.stop_channel(channel)
+    };
+
+    for channel in 0..9 {
+        opl.setup_melody_instrument(channel, presets::ELPIANO1)?;
+        tone(&mut opl, channel, ExerciseStep::Channel(channel))?;
+    }
+
+    opl.ll()
+        .waveform_select_enable()
+        .modify(|_, w| w.waveform_select_enable(Bit::Set))?;
+    for waveform in [
+        WaveformType::Sine,
+        WaveformType::HalfSine,
+        WaveformType::AbsSine,
+        WaveformType::PulseSine,
+    ]
+    .iter()
+    {
+        let mut instrument = presets::ELPIANO1;
+        instrument.operator_0.operator_settings4 =
+            instrument.operator_0.operator_settings4.waveform(*waveform);
+        instrument.operator_1.operator_settings4 =
+            instrument.operator_1.operator_settings4.waveform(*waveform);
+        opl.setup_melody_instrument(0, instrument)?;
+        tone(&mut opl, 0, ExerciseStep::Waveform(*waveform))?;
+    }
+    opl.ll()
+        .waveform_select_enable()
+        .modify(|_, w| w.waveform_select_enable(Bit::Cleared))?;
+
+    #[cfg(feature = "rhythm")]
+    let opl = exercise_drums(opl, delay, &mut on_step)?;
+
+    Ok(opl)
+}
+
+#[cfg(feature = "rhythm")]
+fn exercise_drums<I: HardwareInterface, D: DelayMs<u8>>(
+    mut opl: Opl2<I, Melody>,
+    delay: &mut D,
+    on_step: &mut impl FnMut(ExerciseStep),
+) -> Result<Opl2<I, Melody>, Opl2Error> {
+    // The drums take their pitch from the channels 6-8. Going to rhythm mode turns these notes off again.
+    opl.start_channel(6, Note::C(2))?;
+    opl.start_channel(7, Note::C(4))?;
+    opl.start_channel(8, Note::C(4))?;
+
+    let mut opl = opl.into_rhythm_mode()?;
+    opl.setup_bass_drum(presets::drums::BDRUM1)?;
+    opl.setup_snare_drum(presets::drums::SNARE1)?;
+    opl.setup_tom_tom(presets::drums::TOM1)?;
+    opl.setup_cymbal(presets::drums::CYMBAL1)?;
+    opl.setup_hi_hat(presets::drums::HIHAT1)?;
+
+    type DrumSwitch<I> = fn(&mut Opl2<I, Rhythm>, bool) -> Result<(), Opl2Error>;
+    let drums: [(ExerciseStep, DrumSwitch<I>); 5] = [
+        (ExerciseStep::BassDrum, Opl2::bass_drum),
+        (ExerciseStep::SnareDrum, Opl2::snare_drum),
+        (ExerciseStep::TomTom, Opl2::tom_tom),
+        (ExerciseStep::Cymbal, Opl2::cymbal),
+        (ExerciseStep::HiHat, Opl2::hi_hat),
+    ];
+    for (step, drum) in drums.iter() {
+        on_step(*step);
+        drum(&mut opl, true)?;
+        delay.delay_ms(200);
+        drum(&mut opl, false)?;
+    }
+
+    opl.into_melody_mode()
+}
@@ -0,0 +1,139 @@
+use crate::hl::{Melody, Note, Opl2, Opl2Error};
+use crate::instrument::MelodyInstrument;
+use crate::ll::{ChannelId, HardwareInterface};
+
+/// The chips of a [DualOpl2] that a sound plays on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pan {
+    Left,
+    Right,
+    /// Both chips
+    Center,
+}
+
+/// Two chips as one stereo synth, like the two YM3812s of the Sound Blaster Pro 1.0.
+///
+/// The left chip plays channels 0-8 and the right chip channels 9-17, so every channel is hard panned.
+/// A sound in the center is played on a channel of both chips.
+/// The chips may use different interfaces, for example two boards with their own latch pin on one SPI bus.
+pub struct DualOpl2<IL: HardwareInterface, IR: HardwareInterface> {
+    left: Opl2<IL, Melody>,
+    right: Opl2<IR, Melody>,
+}
+
+impl<IL: HardwareInterface, IR: HardwareInterface> DualOpl2<IL, IR> {
+    /// The amount of channels of both chips together
+    pub const CHANNEL_COUNT: usize = ChannelId::COUNT * 2;
+
+    /// Creates the stereo synth out of two initialized chips
+    pub fn new(left: Opl2<IL, Melody>, right: Opl2<IR, Melody>) -> Self {
+        Self { left, right }
+    }
+
+    pub fn free(self) -> (Opl2<IL, Melody>, Opl2<IR, Melody>) {
+        (self.left, self.right)
+    }
+
+    /// Direct access to the left chip, for example to set up its rhythm section
+    pub fn left(&mut self) -> &mut Opl2<IL, Melody> {
+        &mut self.left
+    }
+
+    /// Direct access to the right chip
+    pub fn right(&mut self) -> &mut Opl2<IR, Melody> {
+        &mut self.right
+    }
+
+    /// The side the channel plays on, or `None` if the channel doesn't exist
+    pub const fn pan(channel: usize) -> Option<Pan> {
+        if channel < ChannelId::COUNT {
+            Some(Pan::Left)
+        } else if channel < Self::CHANNEL_COUNT {
+            Some(Pan::Right)
+        } else {
+            None
+        }
+    }
+
+    pub fn setup_melody_instrument(
+        &mut self,
+        channel: usize,
+        value: MelodyInstrument,
+    ) -> Result<(), Opl2Error> {
+        match Self::pan(channel) {
+            Some(Pan::Left) => self.left.setup_melody_instrument(channel, value),
+            Some(_) => self
+                .right
+                .setup_melody_instrument(channel - ChannelId::COUNT, value),
+            None => Err(Opl2Error::InvalidChannel),
+        }
+    }
+
+    pub fn play_note(
+        &mut self,
+        channel: usize,
+        instrument: &MelodyInstrument,
+        note: Note,
+    ) -> Result<(), Opl2Error> {
+        match Self::pan(channel) {
+            Some(Pan::Left) => self.left.play_note(channel, instrument, note),
+            Some(_) => self
+                .right
+                .play_note(channel - ChannelId::COUNT, instrument, note),
+            None => Err(Opl2Error::InvalidChannel),
+        }
+    }
+
+    pub fn start_channel(&mut self, channel: usize, note: Note) -> Result<(), Opl2Error> {
+        match Self::pan(channel) {
+            Some(Pan::Left) => self.left.start_channel(channel, note),
+            Some(_) => self.right.start_channel(channel - ChannelId::COUNT, note),
+            None => Err(Opl2Error::InvalidChannel),
+        }
+    }
+
+    pub fn stop_channel(&mut self, channel: usize) -> Result<(), Opl2Error> {
+        match Self::pan(channel) {
+            Some(Pan::Left) => self.left.stop_channel(channel),
+            Some(_) => self.right.stop_channel(channel - ChannelId::COUNT),
+            None => Err(Opl2Error::InvalidChannel),
+        }
+    }
+
+    /// Sets up the instrument on the channel (0-8) of the chips that the pan selects.
+    ///
+    /// With [Pan::Center] both chips get the instrument on that channel.
+    pub fn setup_panned_instrument(
+        &mut self,
+        channel: usize,
+        pan: Pan,
+        value: MelodyInstrument,
+    ) -> Result<(), Opl2Error> {
+        if pan != Pan::Right {
+            self.left.setup_melody_instrument(channel, value)?;
+        }
+        if pan != Pan::Left {
+            self.right.setup_melody_instrument(channel, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the note on the channel (0-8) of the chips that the pan selects
+    pub fn start_panned(&mut self, channel: usize, pan: Pan, note: Note) -> Result<(), Opl2Error> {
+        if pan != Pan::Right {
+            self.left.start_channel(channel, note)?;
+        }
+        if pan != Pan::Left {
+            self.right.start_channel(channel, note)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops the channel (0-8) on both chips
+    pub fn stop_panned(&mut self, channel: usize) -> Result<(), Opl2Error> {
+        self.left.stop_channel(channel)?;
+        self.right.stop_channel(channel)
+    }
+}
@@ -0,0 +1,377 @@
+use crate::hl::{Initialized, Note, Opl2, Opl2Error};
+use crate::instrument::MelodyInstrument;
+use crate::ll::{operator_register_index, HardwareInterface, Operator};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+/// The longest response the service can give
+pub const MAX_RESPONSE_LEN: usize = 12;
+
+/// The fields of an instrument that can be edited.
+///
+/// The numbers are part of the protocol, so they must never change.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum Field {
+    AmplitudeModulation = 0x00,
+    Vibrato = 0x01,
+    Sustain = 0x02,
+    KeyboardScalingRate = 0x03,
+    ModulatorFrequencyMultiple = 0x04,
+    LevelKeyScaling = 0x05,
+    OutputLevel = 0x06,
+    AttackRate = 0x07,
+    DecayRate = 0x08,
+    SustainLevel = 0x09,
+    ReleaseRate = 0x0A,
+    Waveform = 0x0B,
+    Feedback = 0x0C,
+    SynthesisType = 0x0D,
+}
+
+impl Field {
+    /// Gives the base register address, the lowest bit and the amount of bits of the field.
+    ///
+    /// These must match the definitions in [crate::ll].
+    /// The waveform keeps its third bit like the register definition, although the chip only hears the lowest 2.
+    const fn location(&self) -> (u8, u8, u8) {
+        match self {
+            Field::AmplitudeModulation => (0x20, 7, 1),
+            Field::Vibrato => (0x20, 6, 1),
+            Field::Sustain => (0x20, 5, 1),
+            Field::KeyboardScalingRate => (0x20, 4, 1),
+            Field::ModulatorFrequencyMultiple => (0x20, 0, 4),
+            Field::LevelKeyScaling => (0x40, 6, 2),
+            Field::OutputLevel => (0x40, 0, 6),
+            Field::AttackRate => (0x60, 4, 4),
+            Field::DecayRate => (0x60, 0, 4),
+            Field::SustainLevel => (0x80, 4, 4),
+            Field::ReleaseRate => (0x80, 0, 4),
+            Field::Waveform => (0xE0, 0, 3),
+            Field::Feedback => (0xC0, 1, 3),
+            Field::SynthesisType => (0xC0, 0, 1),
+        }
+    }
+
+    /// Returns true if the field is part of the channel settings instead of the operator settings
+    pub const fn is_channel_field(&self) -> bool {
+        matches!(self, Field::Feedback | Field::SynthesisType)
+    }
+}
+
+/// The error codes that can be sent back
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum ErrorCode {
+    UnknownCommand = 0x00,
+    InvalidChannel = 0x01,
+    InvalidOperator = 0x02,
+    InvalidField = 0x03,
+    InvalidValue = 0x04,
+    InvalidNote = 0x05,
+    DeviceError = 0x06,
+}
+
+impl From<Opl2Error> for ErrorCode {
+    fn from(error: Opl2Error) -> Self {
+        match error {
+            Opl2Error::InvalidChannel => ErrorCode::InvalidChannel,
+            _ => ErrorCode::DeviceError,
+        }
+    }
+}
+
+/// A response to a request
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Response {
+    Ok,
+    Value(u8),
+    Patch([u8; 11]),
+    Error(ErrorCode),
+}
+
+impl Response {
+    /// Encodes the response into the buffer and returns the amount of bytes used
+    pub fn encode(&self, buffer: &mut [u8; MAX_RESPONSE_LEN]) -> usize {
+        match self {
+            Response::Ok => {
+                buffer[0] = 0x80;
+                1
+            }
+            Response::Value(value) => {
+                buffer[0] = 0x81;
+                buffer[1] = *value;
+                2
+            }
+            Response::Patch(patch) => {
+                buffer[0] = 0x82;
+                buffer[1..12].copy_from_slice(patch);
+                12
+            }
+            Response::Error(code) => {
+                buffer[0] = 0xFF;
+                buffer[1] = (*code).into();
+                2
+            }
+        }
+    }
+}
+
+/// Lets a desktop patch editor change the sounds of a live device.
+///
+/// Feed it the bytes that come in over serial (or any other byte stream) and send back the responses.
+/// Every request is a command byte followed by a fixed amount of payload bytes:
+///
+/// | Request | Command | Payload | Response |
+/// |---|---|---|---|
+/// | Get field | `0x01` | channel, operator, [Field] | `0x81`, value |
+/// | Set field | `0x02` | channel, operator, [Field], value | `0x80` |
+/// | Load patch | `0x03` | channel, 11 instrument bytes | `0x80` |
+/// | Get patch | `0x04` | channel | `0x82`, 11 instrument bytes |
+/// | Note on | `0x05` | channel, note (0 = C up to 11 = B), octave | `0x80` |
+/// | Note off | `0x06` | channel | `0x80` |
+///
+/// The operator byte is ignored for channel fields.
+/// Fields and patches are those of the instrument, so they don't include the volume, a muted operator
+/// or a stop with a different release. Those keep working on top of the edited instrument.
+/// The instrument bytes are in the same order as [MelodyInstrument::from_bytes] takes them.
+/// Failed requests are answered with `0xFF` followed by an [ErrorCode].
+pub struct PatchEditorService {
+    buffer: [u8; 13],
+    len: usize,
+}
+
+impl PatchEditorService {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; 13],
+            len: 0,
+        }
+    }
+
+    /// Feed the next received byte.
+    ///
+    /// Returns a response when a request has been completed.
+    pub fn feed<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        byte: u8,
+        opl: &mut Opl2<I, INIT>,
+    ) -> Option<Response> {
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        let request_len = match self.buffer[0] {
+            0x01 => 4,
+            0x02 => 5,
+            0x03 => 13,
+            0x04 => 2,
+            0x05 => 4,
+            0x06 => 2,
+            _ => {
+                self.len = 0;
+                return Some(Response::Error(ErrorCode::UnknownCommand));
+            }
+        };
+
+        if self.len < request_len {
+            return None;
+        }
+
+        self.len = 0;
+        Some(match Self::execute(&self.buffer[..request_len], opl) {
+            Ok(response) => response,
+            Err(code) => Response::Error(code),
+        })
+    }
+
+    /// Drop any partially received request
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    fn execute<I: HardwareInterface, INIT: Initialized>(
+        request: &[u8],
+        opl: &mut Opl2<I, INIT>,
+    ) -> Result<Response, ErrorCode> {
+        let channel = request[1] as usize;
+        let channel_id = Opl2::<I, INIT>::melody_channel(channel)?;
+
+        match request[0] {
+            0x01 => {
+                let (address, shift, width) = Self::field_address::<I, INIT>(request)?;
+                let value = opl.read_patch_raw(channel_id, address)?;
+                Ok(Response::Value((value >> shift) & ((1 << width) - 1)))
+            }
+            0x02 => {
+                let (address, shift, width) = Self::field_address::<I, INIT>(request)?;
+                let mask = ((1 << width) - 1) << shift;
+                let value = request[4];
+                if value > mask >> shift {
+                    return Err(ErrorCode::InvalidValue);
+                }
+
+                let old_value = opl.read_patch_raw(channel_id, address)?;
+                opl.write_patch_raw(channel_id, address, (old_value & !mask) | (value << shift))?;
+                Ok(Response::Ok)
+            }
+            0x03 => {
+                let mut patch = [0; 11];
+                patch.copy_from_slice(&request[2..13]);
+                opl.setup_melody_instrument(channel, MelodyInstrument::from_bytes(patch))?;
+                Ok(Response::Ok)
+            }
+            0x04 => {
+                let patch = opl.read_patch_bytes(channel_id)?;
+                Ok(Response::Patch(patch))
+            }
+            0x05 => {
+                let octave = request[3];
+                if octave > 7 {
+                    return Err(ErrorCode::InvalidNote);
+                }
+                let note = Note::from_semitone(request[2], octave).ok_or(ErrorCode::InvalidNote)?;
+                opl.start_channel(channel, note)?;
+                Ok(Response::Ok)
+            }
+            0x06 => {
+                opl.stop_channel(channel)?;
+                Ok(Response::Ok)
+            }
+            _ => Err(ErrorCode::UnknownCommand),
+        }
+    }
+
+    /// Gets the register address, lowest bit and width of the field that is addressed by a get or set field request
+    fn field_address<I: HardwareInterface, INIT: Initialized>(
+        request: &[u8],
+    ) -> Result<(u8, u8, u8), ErrorCode> {
+        use core::convert::TryFrom;
+
+        let channel = Opl2::<I, INIT>::melody_channel(request[1] as usize)?;
+        let field = Field::try_from(request[3]).map_err(|_| ErrorCode::InvalidField)?;
+        let (base, shift, width) = field.location();
+
+        let offset = if field.is_channel_field() {
+            channel.index()
+        } else {
+            let operator =
+                Operator::try_from(request[2]).map_err(|_| ErrorCode::InvalidOperator)?;
+            operator_register_index(channel, operator)
+        };
+
+        Ok((base + offset as u8, shift, width))
+    }
+}
+
+impl Default for PatchEditorService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One of the two instruments of an [AbCompare]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub const fn other(&self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    const fn index(&self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// Two versions of an instrument to switch between while designing it, to hear which one is better.
+///
+/// Switching only rewrites the instrument registers, so a note that is playing keeps playing with the other sound.
+pub struct AbCompare {
+    slots: [Option<MelodyInstrument>; 2],
+    live: Option<Slot>,
+}
+
+impl AbCompare {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None, None],
+            live: None,
+        }
+    }
+
+    /// Stores the instrument that is on the channel right now into the slot, which becomes the live one.
+    ///
+    /// The levels and release are the ones of the instrument, so a volume, a muted operator
+    /// or a stop with a different release isn't stored with it.
+    pub fn store<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        channel: usize,
+        slot: Slot,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Opl2::<I, INIT>::melody_channel(channel)?;
+        let instrument = MelodyInstrument::from_bytes(opl.read_patch_bytes(channel_id)?);
+
+        self.slots[slot.index()] = Some(instrument);
+        self.live = Some(slot);
+        Ok(())
+    }
+
+    /// Puts the instrument into the slot without loading it
+    pub fn set(&mut self, slot: Slot, instrument: MelodyInstrument) {
+        self.slots[slot.index()] = Some(instrument);
+    }
+
+    pub fn get(&self, slot: Slot) -> Option<MelodyInstrument> {
+        self.slots[slot.index()]
+    }
+
+    /// The slot that was loaded on the channel last
+    pub fn live(&self) -> Option<Slot> {
+        self.live
+    }
+
+    /// Loads the instrument of the slot on the channel. Returns false if the slot is empty.
+    pub fn select<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        channel: usize,
+        slot: Slot,
+    ) -> Result<bool, Opl2Error> {
+        let instrument = match self.slots[slot.index()] {
+            Some(instrument) => instrument,
+            None => return Ok(false),
+        };
+
+        opl.setup_melody_instrument(channel, instrument)?;
+        self.live = Some(slot);
+        Ok(true)
+    }
+
+    /// Loads the other slot than the live one and returns the slot that is live afterwards.
+    ///
+    /// Nothing changes if the other slot is empty. Without a live slot, A is loaded.
+    pub fn toggle<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        channel: usize,
+    ) -> Result<Option<Slot>, Opl2Error> {
+        let next = self.live.map_or(Slot::A, |slot| slot.other());
+        self.select(opl, channel, next)?;
+        Ok(self.live)
+    }
+}
+
+impl Default for AbCompare {
+    fn default() -> Self {
+        Self::new()
+    }
+}
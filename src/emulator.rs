@@ -0,0 +1,349 @@
+//! A software OPL2, so instruments and sequences can be tried on a desktop or in CI without the board.
+//!
+//! The emulation follows the structure of the chip: every operator has a phase generator, an envelope generator
+//! and a waveform, and the channels combine their two operators with FM or additive synthesis.
+//! It's meant for hearing what the driver does, not for cycle exact playback. These parts of the chip are not emulated:
+//!
+//! - The percussion mode. Channels 6-8 keep playing as melody channels.
+//! - Tremolo, vibrato and the composite sine wave mode.
+//! - The timers and the status register.
+
+use crate::hl::Opl2;
+use crate::ll::{operator_register_index, ChannelId, HardwareInterface, InterfaceError, Operator};
+use device_driver::ll::register::RegisterInterface;
+
+/// The sample rate the chip renders at: the 3.58 MHz clock divided by 72
+pub const SAMPLE_RATE: u32 = 49716;
+
+/// The highest attenuation of the envelope, in steps of 0.1875 dB
+const SILENT: u16 = 511;
+
+/// The first quarter of a sine wave with an amplitude of 4095, at the middle of every step
+const QUARTER_SINE: [i16; 256] = {
+    // π in 2.30 fixed point
+    const PI: i128 = 3_373_259_426;
+    const ONE: i128 = 1 << 30;
+
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let x = (2 * i as i128 + 1) * PI / 1024;
+        let x2 = x * x / ONE;
+
+        // The Taylor series up to x^9 is exact enough for a quarter period
+        let mut term = x;
+        let mut sine = x;
+        let mut n = 1;
+        while n < 5 {
+            term = -term * x2 / ONE / ((2 * n) * (2 * n + 1));
+            sine += term;
+            n += 1;
+        }
+
+        table[i] = ((sine * 4095 + ONE / 2) / ONE) as i16;
+        i += 1;
+    }
+
+    table
+};
+
+/// `4096 * 2^(-i / 32)`, the gain of the fractional part of an attenuation in steps of 0.1875 dB
+const GAIN: [u16; 32] = [
+    4096, 4008, 3922, 3838, 3756, 3676, 3597, 3520, 3444, 3371, 3298, 3228, 3158, 3091, 3025, 2960,
+    2896, 2834, 2774, 2714, 2656, 2599, 2543, 2489, 2435, 2383, 2332, 2282, 2233, 2186, 2139, 2093,
+];
+
+/// The frequency multiples of the operators, times two
+const MULTIPLE_X2: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 16, 20, 20, 24, 24, 30, 30];
+
+/// The attenuation of the key scale level at 6 dB per octave in block 7, by the top 4 bits of the frequency number
+const KEY_SCALE: [u16; 16] = [
+    0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64,
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct OperatorState {
+    /// The position in the waveform, one period is 2^20
+    phase: u32,
+    /// The attenuation of the envelope, 0 is the loudest
+    level: u16,
+    stage: EnvelopeStage,
+    /// The last two outputs, for the feedback of the modulator
+    outputs: [i32; 2],
+}
+
+impl OperatorState {
+    const fn new() -> Self {
+        Self {
+            phase: 0,
+            level: SILENT,
+            stage: EnvelopeStage::Release,
+            outputs: [0; 2],
+        }
+    }
+}
+
+/// An OPL2 that only exists in memory and renders its output as PCM samples.
+///
+/// Use it like any other interface and call [Opl2::render] to get the audio that the chip would have made.
+/// The output is mono at [SAMPLE_RATE].
+pub struct EmulatedInterface {
+    registers: [u8; 256],
+    operators: [OperatorState; ChannelId::COUNT * 2],
+    /// The amount of rendered samples, which times the envelopes
+    sample_counter: u32,
+}
+
+impl EmulatedInterface {
+    pub const fn new() -> Self {
+        Self {
+            registers: [0; 256],
+            operators: [OperatorState::new(); ChannelId::COUNT * 2],
+            sample_counter: 0,
+        }
+    }
+
+    /// Renders the next samples of the chip output
+    pub fn render(&mut self, buffer: &mut [i16]) {
+        for sample in buffer.iter_mut() {
+            let mut mix = 0;
+            for channel in (0..ChannelId::COUNT).filter_map(ChannelId::new) {
+                mix += self.render_channel(channel);
+            }
+
+            *sample = mix.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            self.sample_counter = self.sample_counter.wrapping_add(1);
+        }
+    }
+
+    fn render_channel(&mut self, channel: ChannelId) -> i32 {
+        let index = channel.index();
+        let feedback = (self.registers[0xC0 + index] >> 1) & 0x07;
+        let additive = self.registers[0xC0 + index] & 0x01 != 0;
+
+        let modulator = &self.operators[index * 2];
+        let modulation = if feedback == 0 {
+            0
+        } else {
+            (modulator.outputs[0] + modulator.outputs[1]) >> (9 - feedback)
+        };
+
+        let modulator_output = self.render_operator(channel, Operator::Modulator, modulation);
+        let modulator = &mut self.operators[index * 2];
+        modulator.outputs = [modulator.outputs[1], modulator_output];
+
+        if additive {
+            modulator_output + self.render_operator(channel, Operator::Carrier, 0)
+        } else {
+            self.render_operator(channel, Operator::Carrier, modulator_output)
+        }
+    }
+
+    /// Advances the operator by one sample and gives its output
+    fn render_operator(&mut self, channel: ChannelId, operator: Operator, modulation: i32) -> i32 {
+        let r = &self.registers;
+        let offset = operator_register_index(channel, operator);
+        let characteristic = r[0x20 + offset];
+        let levels = r[0x40 + offset];
+        let attack_decay = r[0x60 + offset];
+        let sustain_release = r[0x80 + offset];
+        let waveform = if r[0x01] & 0x20 != 0 {
+            r[0xE0 + offset] & 0x03
+        } else {
+            0
+        };
+
+        let frequency_number =
+            ((r[0xB0 + channel.index()] as u32 & 0x03) << 8) | r[0xA0 + channel.index()] as u32;
+        let block = (r[0xB0 + channel.index()] >> 2) & 0x07;
+
+        // The key scale rate is in half blocks, the note select picks the bit of the frequency number for the odd half
+        let note_select_bit = if r[0x08] & 0x40 != 0 { 8 } else { 9 };
+        let key_scale = (block << 1) | ((frequency_number >> note_select_bit) & 0x01) as u8;
+        let key_scale = if characteristic & 0x10 != 0 {
+            key_scale
+        } else {
+            key_scale >> 2
+        };
+
+        let sustain_level = match sustain_release >> 4 {
+            15 => 31 * 16,
+            level => level as u16 * 16,
+        };
+        let sustained = characteristic & 0x20 != 0;
+
+        let counter = self.sample_counter;
+        let state = &mut self.operators[channel.index() * 2 + operator as usize];
+
+        let rate = match state.stage {
+            EnvelopeStage::Attack => attack_decay >> 4,
+            EnvelopeStage::Decay => attack_decay & 0x0F,
+            EnvelopeStage::Sustain if sustained => 0,
+            EnvelopeStage::Sustain | EnvelopeStage::Release => sustain_release & 0x0F,
+        };
+        let steps = envelope_steps(rate, key_scale, counter);
+
+        match state.stage {
+            EnvelopeStage::Attack if rate == 15 => state.level = 0,
+            EnvelopeStage::Attack => {
+                for _ in 0..steps {
+                    state.level = state.level.saturating_sub((state.level >> 3) + 1);
+                }
+            }
+            _ => state.level = (state.level + steps).min(SILENT),
+        }
+
+        if state.stage == EnvelopeStage::Attack && state.level == 0 {
+            state.stage = EnvelopeStage::Decay;
+        }
+        if state.stage == EnvelopeStage::Decay && state.level >= sustain_level {
+            state.stage = EnvelopeStage::Sustain;
+        }
+
+        let total_level = (levels & 0x3F) as u16 * 4;
+        let key_scale_level = match levels >> 6 {
+            0 => 0,
+            // The values of the register are in the order 0, 3, 1.5 and 6 dB per octave
+            scaling => {
+                let attenuation = (KEY_SCALE[(frequency_number >> 6) as usize] * 4)
+                    .saturating_sub((8 - block as u16) * 32);
+                match scaling {
+                    1 => attenuation >> 1,
+                    2 => attenuation >> 2,
+                    _ => attenuation,
+                }
+            }
+        };
+        let attenuation = state.level + total_level + key_scale_level;
+
+        let increment =
+            (frequency_number << block) * MULTIPLE_X2[(characteristic & 0x0F) as usize] / 2;
+        let position = ((state.phase >> 10) as i32 + modulation) as u32 & 0x3FF;
+        state.phase = state.phase.wrapping_add(increment) & 0xF_FFFF;
+
+        if attenuation >= 32 * 13 {
+            // Below the resolution of the output
+            return 0;
+        }
+
+        let gain = (GAIN[(attenuation & 0x1F) as usize] >> (attenuation >> 5)) as i32;
+        wave(waveform, position) * gain / 4096
+    }
+}
+
+impl Default for EmulatedInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<STATE> Opl2<EmulatedInterface, STATE> {
+    /// Renders the next samples of the emulated chip, see [EmulatedInterface::render]
+    pub fn render(&mut self, buffer: &mut [i16]) {
+        self.interface_mut().render(buffer)
+    }
+}
+
+/// How many steps the envelope moves in this sample for the rate of the register
+fn envelope_steps(rate: u8, key_scale: u8, counter: u32) -> u16 {
+    if rate == 0 {
+        return 0;
+    }
+
+    let rate = (rate * 4 + key_scale).min(63);
+    let (high, low) = ((rate >> 2) as u32, (rate & 0x03) as u32);
+
+    if high < 13 {
+        // One step every period samples
+        let period = (4 << (13 - high)) / (4 + low);
+        (counter.checked_rem(period) == Some(0)) as u16
+    } else {
+        (((4 + low) << (high - 13)) / 4) as u16
+    }
+}
+
+/// The value of the waveform at the position, where a period has 1024 positions
+fn wave(waveform: u8, position: u32) -> i32 {
+    let quarter = |position: u32| {
+        let index = if position & 0x100 == 0 {
+            position & 0xFF
+        } else {
+            0xFF - (position & 0xFF)
+        };
+        QUARTER_SINE[index as usize] as i32
+    };
+    let sine = |position: u32| {
+        if position & 0x200 == 0 {
+            quarter(position)
+        } else {
+            -quarter(position)
+        }
+    };
+
+    match waveform {
+        0 => sine(position),
+        1 if position & 0x200 == 0 => sine(position),
+        2 => quarter(position),
+        3 if position & 0x100 == 0 => quarter(position),
+        _ => 0,
+    }
+}
+
+impl HardwareInterface for EmulatedInterface {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        *self = Self::new();
+        Ok(())
+    }
+}
+
+impl RegisterInterface for EmulatedInterface {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        value
+            .copy_from_slice(&self.registers[(address as usize)..(address as usize + value.len())]);
+        Ok(())
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        for (i, val) in value.iter().enumerate() {
+            let address = address as usize + i;
+            let previous = self.registers[address];
+            self.registers[address] = *val;
+
+            // The key-on bit starts and releases the envelopes of both operators
+            if let Some(channel) = address.checked_sub(0xB0).and_then(ChannelId::new) {
+                let (was_on, is_on) = (previous & 0x20 != 0, val & 0x20 != 0);
+                let operators = &mut self.operators[channel.index() * 2..channel.index() * 2 + 2];
+
+                for operator in operators.iter_mut() {
+                    if is_on && !was_on {
+                        operator.phase = 0;
+                        operator.stage = EnvelopeStage::Attack;
+                    } else if was_on && !is_on {
+                        operator.stage = EnvelopeStage::Release;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
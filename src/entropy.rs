@@ -0,0 +1,53 @@
+/// A source of random numbers.
+///
+/// Anything in the driver that needs randomness takes an `Entropy`, so no specific RNG is forced on anyone.
+/// With the `rand_core` feature every [rand_core::RngCore] is an `Entropy`.
+/// Otherwise [XorShift] can be used, which is small and deterministic.
+pub trait Entropy {
+    fn next_u32(&mut self) -> u32;
+
+    /// A random number in `0..bound`, or 0 when the bound is 0
+    fn next_below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            return 0;
+        }
+
+        // Multiply-shift keeps the bias low without a division
+        ((self.next_u32() as u64 * bound as u64) >> 32) as u32
+    }
+}
+
+/// A 32-bit xorshift generator.
+///
+/// Not suitable for anything that has to be unpredictable, but the same seed always gives the same numbers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct XorShift {
+    state: u32,
+}
+
+impl XorShift {
+    pub const fn new(seed: u32) -> Self {
+        // Xorshift gets stuck on 0
+        Self {
+            state: if seed == 0 { 1 } else { seed },
+        }
+    }
+}
+
+impl Entropy for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
+
+#[cfg(feature = "rand_core")]
+impl<R: rand_core::RngCore> Entropy for R {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::RngCore::next_u32(self)
+    }
+}
@@ -0,0 +1,100 @@
+//! Stand-ins for the HAL types, so examples and quick-starts compile and run without any hardware.
+//!
+//! ```
+//! use opl_driver::examples_support::{DummyDelay, DummyPin, DummySpi, LoopbackInterface};
+//! use opl_driver::hl::{Note, Opl2};
+//! use opl_driver::instrument::presets;
+//! use opl_driver::ll::ShiftInterface;
+//!
+//! // This is how it's wired up on a real board
+//! let _board = ShiftInterface::new(DummySpi, DummyPin, DummyPin, DummyPin, DummyDelay);
+//!
+//! // And this is a chip that only exists in memory
+//! let mut opl = Opl2::new(LoopbackInterface::new()).initialize().unwrap();
+//! opl.setup_melody_instrument(0, presets::ELPIANO1).unwrap();
+//! opl.start_channel(0, Note::A(4)).unwrap();
+//! ```
+
+use crate::ll::{InterfaceError, MemoryInterface, RecordingInterface, WriteRecorder};
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::v2::OutputPin;
+
+/// A delay that returns immediately
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DummyDelay;
+
+impl DelayUs<u8> for DummyDelay {
+    fn delay_us(&mut self, _us: u8) {}
+}
+
+impl DelayUs<u16> for DummyDelay {
+    fn delay_us(&mut self, _us: u16) {}
+}
+
+impl DelayMs<u8> for DummyDelay {
+    fn delay_ms(&mut self, _ms: u8) {}
+}
+
+impl DelayMs<u16> for DummyDelay {
+    fn delay_ms(&mut self, _ms: u16) {}
+}
+
+/// An output pin that isn't connected to anything
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DummyPin;
+
+impl OutputPin for DummyPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// An SPI bus that drops everything that is written to it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DummySpi;
+
+impl Write<u8> for DummySpi {
+    type Error = Infallible;
+
+    fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A chip that only exists in memory.
+///
+/// Everything that is written can be read back, so it behaves like a [crate::ll::ShiftInterface]
+/// without the hardware. The amount of written registers is counted to see what the driver does.
+pub type LoopbackInterface = RecordingInterface<MemoryInterface, WriteCounter>;
+
+impl LoopbackInterface {
+    pub const fn new() -> Self {
+        Self::with_recorder(MemoryInterface::new(), WriteCounter { count: 0 })
+    }
+
+    /// The amount of registers that have been written since the creation
+    pub fn write_count(&self) -> usize {
+        self.recorder().count
+    }
+}
+
+/// Counts the register writes of the [LoopbackInterface]
+#[derive(Debug, Default)]
+pub struct WriteCounter {
+    count: usize,
+}
+
+impl WriteRecorder for WriteCounter {
+    fn record(&mut self, _address: u8, _value: u8) -> Result<(), InterfaceError> {
+        self.count += 1;
+        Ok(())
+    }
+}
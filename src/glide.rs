@@ -0,0 +1,98 @@
+use crate::hl::{Initialized, Note, Opl2, Opl2Error};
+use crate::ll::HardwareInterface;
+use crate::tuning::{ChipClock, Pitch};
+
+/// Slides the pitch of a channel from one note to another, also known as portamento.
+///
+/// The slide is even in cents, so every semitone takes the same time.
+/// Call [Self::tick] regularly with the time that has passed, for example from a timer interrupt.
+/// The registers are only written when the frequency number changes.
+pub struct Glide {
+    channel: usize,
+    from: Pitch,
+    /// The distance from the first note to the last one
+    cents: i64,
+    duration_us: u32,
+    elapsed_us: u32,
+    /// The frequency number and block that are playing right now
+    playing: Option<(u16, u8)>,
+}
+
+impl Glide {
+    pub fn new(channel: usize, from: Note, to: Note, duration_us: u32) -> Self {
+        let key = |note: Note| note.get_octave() as i64 * 12 + note.semitone() as i64;
+
+        Self {
+            channel,
+            from: Pitch::of_note(from),
+            cents: (key(to) - key(from)) * 100,
+            duration_us,
+            elapsed_us: 0,
+            playing: None,
+        }
+    }
+
+    /// Starts the channel on the first note, or moves it there if it was already playing.
+    ///
+    /// The instrument has to be set up on the channel already.
+    pub fn start<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        clock: &ChipClock,
+    ) -> Result<(), Opl2Error> {
+        self.elapsed_us = 0;
+        self.update(opl, clock, true)
+    }
+
+    /// Moves the slide forward by the time that has passed. Returns true when the last note has been reached.
+    ///
+    /// Only the pitch is changed, so a channel that has been stopped during the slide stays stopped.
+    /// The channel keeps playing the last note after the slide.
+    pub fn tick<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        elapsed_us: u32,
+        clock: &ChipClock,
+    ) -> Result<bool, Opl2Error> {
+        self.elapsed_us = self
+            .elapsed_us
+            .saturating_add(elapsed_us)
+            .min(self.duration_us);
+        self.update(opl, clock, false)?;
+
+        Ok(self.is_finished())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed_us >= self.duration_us
+    }
+
+    /// The pitch the slide is at
+    pub fn pitch(&self) -> Pitch {
+        let cents = match self.duration_us {
+            0 => self.cents,
+            duration => self.cents * self.elapsed_us as i64 / duration as i64,
+        };
+        self.from.transposed(cents)
+    }
+
+    /// Writes the pitch of the slide, with a key-on if `start` is set
+    fn update<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        clock: &ChipClock,
+        start: bool,
+    ) -> Result<(), Opl2Error> {
+        let registers = clock.saturating_frequency_number(self.pitch().millihertz());
+
+        if start {
+            self.playing = Some(registers);
+            opl.start_channel_frequency(self.channel, registers.0, registers.1)?;
+        } else if self.playing != Some(registers) {
+            self.playing = Some(registers);
+            opl.set_channel_frequency(self.channel, registers.0, registers.1)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,13 +1,20 @@
+#[cfg(feature = "rhythm")]
 use crate::{
-    instrument::BassDrum, instrument::Cymbal, instrument::HiHat, instrument::MelodyInstrument,
-    instrument::OperatorSettings, instrument::SnareDrum, instrument::TomTom, ll,
+    instrument::channel_settings2_to_byte, instrument::BassDrum, instrument::Cymbal,
+    instrument::DrumKit, instrument::HiHat, instrument::OperatorSettings, instrument::SnareDrum,
+    instrument::TomTom, ll::registers::channel_settings2, ll::InstrumentMode,
+};
+use crate::{
+    instrument::MelodyInstrument, ll, ll::operator_register_index, ll::ChannelId, ll::Operator,
+    planner::WritePlanner, tuning::FnumBlock,
 };
 use core::marker::PhantomData;
 use device_driver::{ll::LowLevelDevice, Bit};
-use ll::InstrumentMode;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
 pub struct Uninitialized;
 pub struct Melody;
+#[cfg(feature = "rhythm")]
 pub struct Rhythm;
 
 pub trait Initialized {
@@ -17,6 +24,7 @@ pub trait Initialized {
 impl Initialized for Melody {
     const CHANNEL_COUNT: usize = 9;
 }
+#[cfg(feature = "rhythm")]
 impl Initialized for Rhythm {
     const CHANNEL_COUNT: usize = 6;
 }
@@ -25,11 +33,24 @@ impl Initialized for Rhythm {
 pub enum Opl2Error {
     LowLevelError(ll::LowLevelError),
     InvalidChannel,
+    /// The output of a formatting function failed
+    FormatError,
+}
+
+impl From<core::fmt::Error> for Opl2Error {
+    fn from(_: core::fmt::Error) -> Self {
+        Opl2Error::FormatError
+    }
 }
 
 impl<LLE: Into<ll::LowLevelError>> From<LLE> for Opl2Error {
     fn from(low_level_error: LLE) -> Self {
-        Opl2Error::LowLevelError(low_level_error.into())
+        let error = low_level_error.into();
+        match &error {
+            ll::LowLevelError::InterfaceError(error) => trace!("interface error: {}", error.name()),
+            ll::LowLevelError::ConversionError => trace!("register conversion error"),
+        }
+        Opl2Error::LowLevelError(error)
     }
 }
 
@@ -50,6 +71,64 @@ pub enum Note {
 }
 
 impl Note {
+    /// Creates the note from its semitone in the octave (0 = C up to 11 = B).
+    ///
+    /// Returns `None` if the semitone is 12 or higher.
+    pub const fn from_semitone(semitone: u8, octave: u8) -> Option<Self> {
+        Some(match semitone {
+            0 => Note::C(octave),
+            1 => Note::Cs(octave),
+            2 => Note::D(octave),
+            3 => Note::Eb(octave),
+            4 => Note::E(octave),
+            5 => Note::F(octave),
+            6 => Note::Fs(octave),
+            7 => Note::G(octave),
+            8 => Note::Ab(octave),
+            9 => Note::A(octave),
+            10 => Note::Bb(octave),
+            11 => Note::B(octave),
+            _ => return None,
+        })
+    }
+
+    /// Creates the note from a MIDI note number, where 60 is C4 and 69 is A4.
+    ///
+    /// Returns `None` for the notes below C0 (12) and above B7 (107), which are outside of the octaves of the chip.
+    /// [crate::tuning::FnumBlock::from_midi] reaches these too, as far as the chip can play them.
+    pub const fn from_midi(note: u8) -> Option<Self> {
+        match note {
+            12..=107 => Self::from_semitone(note % 12, note / 12 - 1),
+            _ => None,
+        }
+    }
+
+    /// The semitone of the note in its octave (0 = C up to 11 = B)
+    pub const fn semitone(&self) -> u8 {
+        match self {
+            Note::C(_) => 0,
+            Note::Cs(_) => 1,
+            Note::D(_) => 2,
+            Note::Eb(_) => 3,
+            Note::E(_) => 4,
+            Note::F(_) => 5,
+            Note::Fs(_) => 6,
+            Note::G(_) => 7,
+            Note::Ab(_) => 8,
+            Note::A(_) => 9,
+            Note::Bb(_) => 10,
+            Note::B(_) => 11,
+        }
+    }
+
+    /// The name of the note without its octave, like `C#`
+    pub const fn name(&self) -> &'static str {
+        const NAMES: [&str; 12] = [
+            "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+        ];
+        NAMES[self.semitone() as usize]
+    }
+
     pub fn get_frequency(&self) -> u16 {
         match self {
             Note::C(_) => 0x157,
@@ -85,74 +164,859 @@ impl Note {
     }
 }
 
+impl core::fmt::Display for Note {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}{}", self.name(), self.get_octave())
+    }
+}
+
+/// The flags of the status register
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Status {
+    /// Set when one of the timer flags is set
+    pub irq: bool,
+    pub timer1_overflow: bool,
+    pub timer2_overflow: bool,
+}
+
+impl Status {
+    pub const fn from_byte(value: u8) -> Self {
+        Self {
+            irq: value & 0x80 != 0,
+            timer1_overflow: value & 0x40 != 0,
+            timer2_overflow: value & 0x20 != 0,
+        }
+    }
+}
+
+/// The chip generations that [Opl2::detect_chip] can tell apart
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChipKind {
+    /// An OPL2 (YM3812) or the OPL (YM3526) that came before it
+    Opl2,
+    /// An OPL3 (YMF262). It starts in OPL2 mode, where it plays like an OPL2, and this driver keeps it there
+    Opl3,
+}
+
+/// The results of [Opl2::self_test]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SelfTestReport {
+    /// The amount of test pattern writes that the interface could not give back.
+    ///
+    /// The registers of the chip can't be read, so this checks the register image of the interface and not the chip.
+    /// 0 means the image works, not that the writes reached the chip.
+    pub register_mismatches: u16,
+    /// Whether timer 1 raised its overflow flag or `None` if the status register could not be read
+    pub timer1_overflow: Option<bool>,
+    /// Whether timer 2 raised its overflow flag or `None` if the status register could not be read
+    pub timer2_overflow: Option<bool>,
+}
+
+impl SelfTestReport {
+    /// Returns true if nothing failed. Checks that could not be done don't count as failures.
+    ///
+    /// Without the timer flags this only covers the register image, which doesn't prove that the board works.
+    pub fn passed(&self) -> bool {
+        self.register_mismatches == 0
+            && self.timer1_overflow != Some(false)
+            && self.timer2_overflow != Some(false)
+    }
+}
+
+/// The most register writes that [Opl2::play_note] does: 11 for the instrument and 2 for the frequency and key-on
+pub const NOTE_ON_MAX_WRITES: usize = 13;
+
+/// The registers that [Opl2::refresh] rewrites.
+///
+/// The timer control register is left out, because rewriting it would restart the timers.
+const REFRESHED_REGISTERS: [(u8, u8); 11] = [
+    (0x01, 0x03),
+    (0x08, 0x08),
+    (0x20, 0x35),
+    (0x40, 0x55),
+    (0x60, 0x75),
+    (0x80, 0x95),
+    (0xA0, 0xA8),
+    (0xB0, 0xB8),
+    (0xBD, 0xBD),
+    (0xC0, 0xC8),
+    (0xE0, 0xF5),
+];
+
+/// The amount of registers in [REFRESHED_REGISTERS]
+pub(crate) const REFRESHED_REGISTER_COUNT: usize = {
+    let mut count = 0;
+    let mut i = 0;
+    while i < REFRESHED_REGISTERS.len() {
+        count += (REFRESHED_REGISTERS[i].1 - REFRESHED_REGISTERS[i].0) as usize + 1;
+        i += 1;
+    }
+    count
+};
+
+/// The registers that are cleared by a reset, in the order in which they are cleared.
+///
+/// Only the registers that exist on the chip are in here. The key-ons go first, so no note keeps sounding while its
+/// instrument is being cleared, and the rhythm register goes last.
+const CLEARED_REGISTERS: [u8; 123] = {
+    const OPERATOR_BASES: [u8; 5] = [0x20, 0x40, 0x60, 0x80, 0xE0];
+
+    let mut registers = [0; 123];
+    let mut len = 0;
+
+    let mut channel = 0;
+    while channel < ChannelId::COUNT {
+        registers[len] = 0xB0 + channel as u8;
+        len += 1;
+        channel += 1;
+    }
+
+    let timer_registers = [0x01, 0x02, 0x03, 0x04, 0x08];
+    let mut i = 0;
+    while i < timer_registers.len() {
+        registers[len] = timer_registers[i];
+        len += 1;
+        i += 1;
+    }
+
+    let mut base = 0;
+    while base < OPERATOR_BASES.len() {
+        let mut channel = 0;
+        while channel < ChannelId::COUNT {
+            let id = match ChannelId::new(channel) {
+                Some(id) => id,
+                None => unreachable!(),
+            };
+            registers[len] =
+                OPERATOR_BASES[base] + operator_register_index(id, Operator::Modulator) as u8;
+            registers[len + 1] =
+                OPERATOR_BASES[base] + operator_register_index(id, Operator::Carrier) as u8;
+            len += 2;
+            channel += 1;
+        }
+        base += 1;
+    }
+
+    let mut channel = 0;
+    while channel < ChannelId::COUNT {
+        registers[len] = 0xA0 + channel as u8;
+        registers[len + 1] = 0xC0 + channel as u8;
+        len += 2;
+        channel += 1;
+    }
+
+    registers[len] = 0xBD;
+    assert!(len + 1 == registers.len());
+
+    registers
+};
+
+/// A register image that is safe to restore to the chip with [Opl2::restore_state]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChipState {
+    registers: [u8; 256],
+}
+
+impl ChipState {
+    /// Takes a register image that may come from anywhere, like an emulator or another tool, and makes it safe.
+    ///
+    /// - Registers that don't exist on the chip are cleared.
+    /// - The test bits are cleared, because they can stop the chip from making sound.
+    /// - The timers are stopped and the IRQ reset bit is cleared. Only the timer masks are kept.
+    /// - The composite sine wave mode is turned off when a channel has its key on, because the chip can't play notes in that mode.
+    /// - The unused bits of the key-on registers are cleared.
+    pub fn from_raw_image(image: &[u8; 256]) -> Self {
+        let mut registers = [0; 256];
+        for address in CLEARED_REGISTERS.iter() {
+            registers[*address as usize] = image[*address as usize];
+        }
+
+        registers[0x01] &= 0x20;
+        registers[0x04] &= 0x60;
+
+        let key_on = registers[0xB0..=0xB8].iter().any(|key| key & 0x20 != 0);
+        registers[0x08] &= if key_on { 0x40 } else { 0xC0 };
+        for key in registers[0xB0..=0xB8].iter_mut() {
+            *key &= 0x3F;
+        }
+
+        Self { registers }
+    }
+
+    pub fn registers(&self) -> &[u8; 256] {
+        &self.registers
+    }
+}
+
+/// One of the two timers of the chip
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Timer {
+    /// Counts in steps of 80 µs
+    Timer1,
+    /// Counts in steps of 320 µs
+    Timer2,
+}
+
+impl Timer {
+    pub const fn resolution_us(&self) -> u32 {
+        match self {
+            Timer::Timer1 => 80,
+            Timer::Timer2 => 320,
+        }
+    }
+
+    /// The preset value that makes the timer overflow with the period closest to the given one.
+    ///
+    /// The timer counts up from the preset to 256, so it can overflow every 1 to 256 steps.
+    /// Returns `None` if the period is longer than 256 steps.
+    pub const fn preset_for_period(&self, period_us: u32) -> Option<u8> {
+        let resolution = self.resolution_us();
+        let steps = period_us.saturating_add(resolution / 2) / resolution;
+        match steps {
+            0 => Some(0xFF),
+            1..=256 => Some((256 - steps) as u8),
+            _ => None,
+        }
+    }
+
+    /// The time between the overflows of the timer with the preset value
+    pub const fn period_us(&self, preset: u8) -> u32 {
+        (256 - preset as u32) * self.resolution_us()
+    }
+
+    /// The start bit of the timer in the timer control register
+    const fn start_bit(&self) -> u8 {
+        match self {
+            Timer::Timer1 => 0x01,
+            Timer::Timer2 => 0x02,
+        }
+    }
+
+    /// The mask bit of the timer in the timer control register
+    const fn mask_bit(&self) -> u8 {
+        match self {
+            Timer::Timer1 => 0x40,
+            Timer::Timer2 => 0x20,
+        }
+    }
+}
+
+/// The writes that bring a chip into a known state after its reset, see [Opl2::initialize_with_warm_up].
+///
+/// The test register is cleared, the timers are stopped and masked and the IRQ is reset.
+/// The last write leaves the timer control register at its reset value, so the register image matches a plain reset.
+const WARM_UP_WRITES: [(u8, u8); 4] = [(0x01, 0x00), (0x04, 0x60), (0x04, 0x80), (0x04, 0x00)];
+
+/// Settings for [Opl2::initialize_with]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct InitConfig {
+    /// Trust the hardware reset to have cleared the registers and don't clear them again over the bus.
+    ///
+    /// The chip clears all its registers when its reset pin is asserted, so this brings the initialization
+    /// from about 4 ms of bus traffic down to only the reset pulse.
+    /// Leave this off when the reset pin of the chip isn't driven by the interface.
+    pub trust_hardware_reset: bool,
+}
+
 pub struct Opl2<I: ll::HardwareInterface, STATE> {
     ll: ll::Opl2LL<I>,
+    /// The amount of [CLEARED_REGISTERS] that [Opl2::reset_step] has cleared, or `None` if the reset hasn't started
+    reset_progress: Option<usize>,
+    /// The index into the [REFRESHED_REGISTERS] where the next [Opl2::refresh] continues
+    refresh_cursor: usize,
+    /// The patch value of the carrier release register of every channel that has a release override
+    release_overrides: [Option<u8>; ChannelId::COUNT],
+    /// The patch values of the modulator and carrier level registers of every channel that has a volume
+    level_overrides: [Option<(u8, u8)>; ChannelId::COUNT],
+    /// The level register values that come back when a muted operator is unmuted, by channel and operator
+    muted_levels: [[Option<u8>; 2]; ChannelId::COUNT],
+    /// The start and mask bits of the timer control register.
+    ///
+    /// The register image can't be used for these, because a reset of the IRQ leaves 0x80 in it while the timers keep running.
+    timer_control: u8,
     phantom: PhantomData<STATE>,
 }
 
+/// The driver on a [ll::DynInterface], which is only compiled once for all interface types
+pub type DynOpl2<'a, STATE> = Opl2<ll::DynInterface<'a>, STATE>;
+
+impl<I: ll::HardwareInterface, STATE> Opl2<I, STATE> {
+    /// Read access to the hardware interface, for example to look at the registers of a [ll::MemoryInterface].
+    ///
+    /// There is no write access, because that would bypass the driver and it would no longer know what the chip is doing.
+    pub fn interface(&mut self) -> &I {
+        self.ll.interface()
+    }
+
+    /// Write access for the wrappers in this crate that know which writes are safe
+    #[cfg(any(test, feature = "async", feature = "emulator", feature = "test-utils"))]
+    pub(crate) fn interface_mut(&mut self) -> &mut I {
+        self.ll.interface()
+    }
+
+    /// Forgets the release, level and mute overrides of the channel.
+    ///
+    /// Must be called whenever the registers of the channel are rewritten, because the overrides keep the
+    /// patch values of the instrument that was there before.
+    fn forget_channel_overrides(&mut self, channel: ChannelId) {
+        self.release_overrides[channel.index()] = None;
+        self.level_overrides[channel.index()] = None;
+        self.muted_levels[channel.index()] = [None; 2];
+    }
+
+    /// Forgets the overrides of all channels, see [Self::forget_channel_overrides]
+    fn forget_all_overrides(&mut self) {
+        for channel in (0..ChannelId::COUNT).filter_map(ChannelId::new) {
+            self.forget_channel_overrides(channel);
+        }
+    }
+
+    /// Clears the next `register_count` registers of the reset, see [Opl2::reset_step]
+    fn advance_reset(&mut self, register_count: usize) -> Result<bool, Opl2Error> {
+        let mut progress = match self.reset_progress {
+            Some(progress) => progress,
+            None => {
+                self.ll.interface().reset()?;
+                0
+            }
+        };
+        self.reset_progress = Some(progress);
+
+        let end = (progress + register_count).min(CLEARED_REGISTERS.len());
+        while progress < end {
+            self.ll
+                .interface()
+                .write_register(CLEARED_REGISTERS[progress], &[0])?;
+            progress += 1;
+            self.reset_progress = Some(progress);
+        }
+
+        Ok(end == CLEARED_REGISTERS.len())
+    }
+
+    /// Finishes the reset that has been started with [Opl2::reset_step], or does all of it if it hasn't started
+    fn finish_reset(&mut self, config: InitConfig) -> Result<(), Opl2Error> {
+        if !config.trust_hardware_reset {
+            while !self.advance_reset(CLEARED_REGISTERS.len())? {}
+        } else if self.reset_progress.is_none() {
+            self.ll.interface().reset()?;
+        }
+
+        self.reset_progress = None;
+        self.refresh_cursor = 0;
+        self.timer_control = 0;
+        self.forget_all_overrides();
+
+        Ok(())
+    }
+}
+
 impl<I: ll::HardwareInterface> Opl2<I, Uninitialized> {
     pub fn new(interface: I) -> Self {
         Self {
             ll: ll::Opl2LL::new(interface),
+            reset_progress: None,
+            refresh_cursor: 0,
+            release_overrides: [None; ChannelId::COUNT],
+            level_overrides: [None; ChannelId::COUNT],
+            muted_levels: [[None; 2]; ChannelId::COUNT],
+            timer_control: 0,
             phantom: PhantomData::default(),
         }
     }
 
-    pub fn initialize(mut self) -> Result<Opl2<I, Melody>, Opl2Error> {
-        self.ll.interface().reset()?;
+    /// Does the next part of the reset, so it can be spread out for systems with tight deadlines.
+    ///
+    /// The first call asserts the reset pin and every call clears the next `register_count` registers.
+    /// Returns true when the reset is done, after which [Self::initialize] doesn't do any more bus traffic.
+    /// Clearing all registers in one go takes about 4 ms with the [ll::ShiftInterface].
+    pub fn reset_step(&mut self, register_count: usize) -> Result<bool, Opl2Error> {
+        self.advance_reset(register_count)
+    }
+
+    /// Resets the chip and clears all registers.
+    ///
+    /// Finishes a reset that has been started with [Self::reset_step].
+    pub fn initialize(self) -> Result<Opl2<I, Melody>, Opl2Error> {
+        self.initialize_with(InitConfig::default())
+    }
+
+    /// Resets the chip with the given config.
+    ///
+    /// Finishes a reset that has been started with [Self::reset_step].
+    pub fn initialize_with(mut self, config: InitConfig) -> Result<Opl2<I, Melody>, Opl2Error> {
+        self.finish_reset(config)?;
+        trace!(
+            "initialized, trusted the hardware reset: {}",
+            config.trust_hardware_reset
+        );
 
         Ok(Opl2 {
             ll: self.ll,
+            reset_progress: None,
+            refresh_cursor: 0,
+            release_overrides: [None; ChannelId::COUNT],
+            level_overrides: [None; ChannelId::COUNT],
+            muted_levels: [[None; 2]; ChannelId::COUNT],
+            timer_control: 0,
             phantom: PhantomData::default(),
         })
     }
+
+    /// Resets the chip in two stages, for clone chips that need a warm-up before they give stable output.
+    ///
+    /// The first stage is the reset itself, after which the chip gets `settle_ms` to settle.
+    /// How long that has to be depends on the chip and the board, so it's up to the caller.
+    /// The second stage clears the test register, stops the timers and resets the IRQ,
+    /// and then finishes like [Self::initialize_with].
+    ///
+    /// This is less than selectable profiles per clone chip. There are no documented warm-up sequences
+    /// to build those from, so every chip gets the same second stage and only the settle time differs.
+    pub fn initialize_with_warm_up<D: DelayMs<u8>>(
+        mut self,
+        config: InitConfig,
+        settle_ms: u8,
+        delay: &mut D,
+    ) -> Result<Opl2<I, Melody>, Opl2Error> {
+        if self.reset_progress.is_none() {
+            self.ll.interface().reset()?;
+            // The reset has been done, the register clearing of the second stage continues after it
+            self.reset_progress = Some(0);
+        }
+
+        if settle_ms > 0 {
+            delay.delay_ms(settle_ms);
+        }
+        self.ll.interface().write_batch(&WARM_UP_WRITES)?;
+
+        self.initialize_with(config)
+    }
 }
 
 impl<I: ll::HardwareInterface, INIT: Initialized> Opl2<I, INIT> {
-    // Map that gives the two operator indices for each channel
-    const OPERATOR_MAP: [(usize, usize); 9] = [
-        (0x00, 0x03),
-        (0x01, 0x04),
-        (0x02, 0x05),
-        (0x08, 0x0B),
-        (0x09, 0x0C),
-        (0x0A, 0x0D),
-        (0x10, 0x13),
-        (0x11, 0x14),
-        (0x12, 0x15),
-    ];
-
     pub fn ll(&mut self) -> ll::registers::RegisterSet<I> {
         self.ll.registers()
     }
 
+    /// Checks that the channel can be used as melody channel in the current mode
+    pub(crate) fn melody_channel(channel: usize) -> Result<ChannelId, Opl2Error> {
+        if channel >= INIT::CHANNEL_COUNT {
+            return Err(Opl2Error::InvalidChannel);
+        }
+
+        ChannelId::new(channel).ok_or(Opl2Error::InvalidChannel)
+    }
+
+    /// Reads the raw value of a register
+    pub(crate) fn read_raw(&mut self, address: u8) -> Result<u8, Opl2Error> {
+        let mut value = [0];
+        self.ll.interface().read_register(address, &mut value)?;
+        Ok(value[0])
+    }
+
+    /// Writes a raw value to a register
+    pub(crate) fn write_raw(&mut self, address: u8, value: u8) -> Result<(), Opl2Error> {
+        self.ll.interface().write_register(address, &[value])?;
+        Ok(())
+    }
+
+    /// Rewrites the next `register_count` registers from the shadow to the chip.
+    ///
+    /// Electrical noise can flip bits in the chip over time. Calling this periodically
+    /// keeps the chip and the shadow the same while bounding the work done per call.
+    /// All 142 registers have been refreshed after `ceil(142 / register_count)` calls.
+    pub fn refresh(&mut self, register_count: usize) -> Result<(), Opl2Error> {
+        let total = REFRESHED_REGISTER_COUNT;
+
+        for _ in 0..register_count.min(total) {
+            let address = REFRESHED_REGISTERS
+                .iter()
+                .flat_map(|(start, end)| *start..=*end)
+                .nth(self.refresh_cursor)
+                .unwrap_or(0x01);
+            self.refresh_cursor = (self.refresh_cursor + 1) % total;
+
+            let value = self.read_raw(address)?;
+            self.write_raw(address, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Silences the chip without a hardware reset.
+    ///
+    /// All operators first get the softest level and the fastest release, then all channels and drums are keyed off,
+    /// so the notes that are still sounding or releasing fade out instead of jumping to full level.
+    /// After that the other registers are cleared like the reset does, which also clears the instruments and timers.
+    /// The operators keep their silent level and release. The percussion mode bit is kept,
+    /// so the chip stays in the mode of this driver.
+    pub fn safe_silence(&mut self) -> Result<(), Opl2Error> {
+        let silenced = |address: u8| match address {
+            0x40..=0x55 => Some(0x3F),
+            0x80..=0x95 => Some(0x0F),
+            _ => None,
+        };
+
+        for address in CLEARED_REGISTERS.iter() {
+            if let Some(value) = silenced(*address) {
+                self.write_raw(*address, value)?;
+            }
+        }
+
+        for address in 0xB0..=0xB8 {
+            self.write_raw(address, 0)?;
+        }
+        // Only keep the percussion mode bit
+        let percussion_mode = self.read_raw(0xBD)? & 0x20;
+        self.write_raw(0xBD, percussion_mode)?;
+
+        for address in CLEARED_REGISTERS.iter() {
+            if matches!(address, 0xB0..=0xB8 | 0xBD) || silenced(*address).is_some() {
+                continue;
+            }
+
+            self.write_raw(*address, 0)?;
+        }
+        self.timer_control = 0;
+        self.forget_all_overrides();
+
+        Ok(())
+    }
+
+    /// Takes the state of all registers, so it can be restored later
+    pub fn capture_state(&mut self) -> Result<ChipState, Opl2Error> {
+        let mut image = [0; 256];
+        for address in CLEARED_REGISTERS.iter() {
+            image[*address as usize] = self.read_raw(*address)?;
+        }
+        image[0x04] = self.timer_control;
+
+        Ok(ChipState::from_raw_image(&image))
+    }
+
+    /// Writes all registers of the state to the chip.
+    ///
+    /// The channels are keyed off while their instruments are written and only keyed on again at the end,
+    /// so no note sounds with half an instrument. The percussion mode bit is kept, so the chip stays in the mode of this driver.
+    pub fn restore_state(&mut self, state: &ChipState) -> Result<(), Opl2Error> {
+        let registers = state.registers();
+
+        for address in CLEARED_REGISTERS.iter() {
+            let value = match address {
+                0xB0..=0xB8 => registers[*address as usize] & !0x20,
+                0xBD => continue,
+                _ => registers[*address as usize],
+            };
+            self.write_raw(*address, value)?;
+        }
+        for address in 0xB0..=0xB8 {
+            self.write_raw(address, registers[address as usize])?;
+        }
+        let percussion_mode = self.read_raw(0xBD)? & 0x20;
+        self.write_raw(0xBD, (registers[0xBD] & !0x20) | percussion_mode)?;
+
+        // The state only has the timer masks, which are the ones that were written
+        self.timer_control = registers[0x04];
+        self.forget_all_overrides();
+
+        Ok(())
+    }
+
+    /// Reads the raw instrument of the channel in the order [MelodyInstrument::from_bytes] takes it
+    pub(crate) fn read_instrument_bytes(
+        &mut self,
+        channel: ChannelId,
+    ) -> Result<[u8; 11], Opl2Error> {
+        let modulator = operator_register_index(channel, Operator::Modulator) as u8;
+        let carrier = operator_register_index(channel, Operator::Carrier) as u8;
+
+        let mut bytes = [0; 11];
+        for (i, base) in [0x20, 0x40, 0x60, 0x80, 0xE0].iter().enumerate() {
+            bytes[i] = self.read_raw(base + modulator)?;
+            bytes[i + 6] = self.read_raw(base + carrier)?;
+        }
+        bytes[5] = self.read_raw(0xC0 + channel.index() as u8)?;
+
+        Ok(bytes)
+    }
+
+    /// Reads the instrument of the channel like [Self::read_instrument_bytes], but with the levels and release
+    /// of the instrument itself instead of the volume, mute and release overrides that are on the chip
+    pub(crate) fn read_patch_bytes(&mut self, channel: ChannelId) -> Result<[u8; 11], Opl2Error> {
+        let mut bytes = self.read_instrument_bytes(channel)?;
+        bytes[1] = self.read_patch_level(channel, Operator::Modulator)?;
+        bytes[7] = self.read_patch_level(channel, Operator::Carrier)?;
+        if let Some(patch_value) = self.release_overrides[channel.index()] {
+            bytes[9] = patch_value;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Reads a register of the instrument of the channel as the instrument has it, see [Self::read_patch_bytes]
+    pub(crate) fn read_patch_raw(
+        &mut self,
+        channel: ChannelId,
+        address: u8,
+    ) -> Result<u8, Opl2Error> {
+        if let Some(operator) = Self::level_operator(channel, address) {
+            return self.read_patch_level(channel, operator);
+        }
+
+        match self.release_overrides[channel.index()] {
+            Some(patch_value) if address == Self::carrier_release_address(channel) => {
+                Ok(patch_value)
+            }
+            _ => self.read_raw(address),
+        }
+    }
+
+    /// Writes a register of the instrument of the channel, while the overrides of the channel keep working on top of it.
+    ///
+    /// A muted operator gets the new level when it's unmuted and a stopped channel gets the new release rate at its
+    /// next start. A new level drops the volume of the channel, like setting up a new instrument does.
+    pub(crate) fn write_patch_raw(
+        &mut self,
+        channel: ChannelId,
+        address: u8,
+        value: u8,
+    ) -> Result<(), Opl2Error> {
+        if let Some(operator) = Self::level_operator(channel, address) {
+            if let Some((modulator, carrier)) = self.level_overrides[channel.index()].take() {
+                self.write_level(channel, Operator::Modulator, modulator)?;
+                self.write_level(channel, Operator::Carrier, carrier)?;
+            }
+            return self.write_level(channel, operator, value);
+        }
+
+        if address == Self::carrier_release_address(channel)
+            && self.release_overrides[channel.index()].is_some()
+        {
+            self.release_overrides[channel.index()] = Some(value);
+            let release_rate = self.read_raw(address)? & 0x0F;
+            return self.write_raw(address, (value & 0xF0) | release_rate);
+        }
+
+        self.write_raw(address, value)
+    }
+
+    /// The operator of the channel that the address is the level register of
+    fn level_operator(channel: ChannelId, address: u8) -> Option<Operator> {
+        [Operator::Modulator, Operator::Carrier]
+            .iter()
+            .copied()
+            .find(|operator| address == 0x40 + operator_register_index(channel, *operator) as u8)
+    }
+
+    /// Writes a table with the state of every channel and the drums, to debug live devices over a serial port or RTT.
+    ///
+    /// Per channel it shows the key, the frequency, the note when the frequency is exactly one, the attenuation
+    /// of the carrier and the raw instrument in the order [MelodyInstrument::from_bytes] takes it.
+    pub fn dump_state(&mut self, out: &mut impl core::fmt::Write) -> Result<(), Opl2Error> {
+        let rhythm = self.read_raw(0xBD)?;
+        let percussion_mode = rhythm & 0x20 != 0;
+
+        writeln!(out, "ch key fnum blk note  att instrument")?;
+        for channel in (0..ChannelId::COUNT).filter_map(ChannelId::new) {
+            let index = channel.index() as u8;
+            let key = self.read_raw(0xB0 + index)?;
+            let frequency_number = ((key as u16 & 0x03) << 8) | self.read_raw(0xA0 + index)? as u16;
+            let block = (key >> 2) & 0x07;
+            let carrier = operator_register_index(channel, Operator::Carrier) as u8;
+            let attenuation = self.read_raw(0x40 + carrier)? & 0x3F;
+
+            let key = match (percussion_mode && channel.index() >= 6, key & 0x20 != 0) {
+                (true, _) => "drm",
+                (false, true) => "on",
+                (false, false) => "off",
+            };
+            let note = (0..12)
+                .filter_map(|semitone| Note::from_semitone(semitone, block))
+                .find(|note| note.get_frequency() == frequency_number);
+
+            write!(
+                out,
+                "{:2} {:3} {:4} {:3} ",
+                index, key, frequency_number, block
+            )?;
+            match note {
+                Some(note) => {
+                    let name = note.name();
+                    write!(
+                        out,
+                        "{}{}{:3$}",
+                        name,
+                        note.get_octave(),
+                        "",
+                        4 - name.len()
+                    )?
+                }
+                None => write!(out, "{:5}", "-")?,
+            }
+            write!(out, " {:3}", attenuation)?;
+            for byte in self.read_instrument_bytes(channel)?.iter() {
+                write!(out, " {:02X}", byte)?;
+            }
+            writeln!(out)?;
+        }
+
+        let flag = |mask: u8| if rhythm & mask != 0 { "on" } else { "off" };
+        writeln!(
+            out,
+            "percussion {} bd {} sd {} tt {} cy {} hh {}",
+            flag(0x20),
+            flag(0x10),
+            flag(0x08),
+            flag(0x04),
+            flag(0x02),
+            flag(0x01)
+        )?;
+
+        Ok(())
+    }
+
+    /// Runs a test of the chip and the interface, meant for production-line testing of boards.
+    ///
+    /// - All operator and channel registers are written with test patterns and read back.
+    ///   Their original values are restored afterwards. The chip's registers can't be read, so this reads back
+    ///   the shadow image of the interface. It finds a broken image, but not a broken bus or chip, so a board
+    ///   with 0 mismatches has not passed. Listen to the test tone and check the timers for that.
+    /// - Both timers are started and stopped again. Their presets and control bits are restored afterwards.
+    ///   The interface can't read the status register, so the overflow flags are only checked by
+    ///   [Opl2::self_test_with_status].
+    /// - A short test tone is played on channel 0, for the person testing the board to hear.
+    ///   The report can't tell whether it sounded. The channel gets its original registers back afterwards
+    ///   and keeps its volume, mutes and release override.
+    pub fn self_test<D: DelayMs<u8>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<SelfTestReport, Opl2Error> {
+        self.run_self_test(delay, |_| Ok(None))
+    }
+
+    fn run_self_test<D: DelayMs<u8>>(
+        &mut self,
+        delay: &mut D,
+        read_status: impl FnOnce(&mut Self) -> Result<Option<Status>, Opl2Error>,
+    ) -> Result<SelfTestReport, Opl2Error> {
+        const TESTED_REGISTERS: [(u8, u8); 7] = [
+            (0x20, 0x35),
+            (0x40, 0x55),
+            (0x60, 0x75),
+            (0x80, 0x95),
+            (0xA0, 0xA8),
+            (0xC0, 0xC8),
+            (0xE0, 0xF5),
+        ];
+
+        let mut report = SelfTestReport {
+            register_mismatches: 0,
+            timer1_overflow: None,
+            timer2_overflow: None,
+        };
+
+        // Only the shadow image answers these reads, the chip itself is never read
+        for address in TESTED_REGISTERS
+            .iter()
+            .flat_map(|(start, end)| *start..=*end)
+        {
+            let original = self.read_raw(address)?;
+            for pattern in [0x55, 0xAA].iter() {
+                self.write_raw(address, *pattern)?;
+                if self.read_raw(address)? != *pattern {
+                    report.register_mismatches += 1;
+                }
+            }
+            self.write_raw(address, original)?;
+        }
+
+        let timer_presets = [self.read_raw(0x02)?, self.read_raw(0x03)?];
+        let timer_control = self.timer_control;
+
+        self.ll().timer1_count().write(|w| w.preset_value(0xFF))?;
+        self.ll().timer2_count().write(|w| w.preset_value(0xFF))?;
+        self.set_timer_control(Timer::Timer1.start_bit() | Timer::Timer2.start_bit())?;
+        delay.delay_ms(1);
+        if let Some(status) = read_status(self)? {
+            report.timer1_overflow = Some(status.timer1_overflow);
+            report.timer2_overflow = Some(status.timer2_overflow);
+        }
+        self.set_timer_control(Timer::Timer1.mask_bit() | Timer::Timer2.mask_bit())?;
+        self.clear_irq()?;
+        self.write_raw(0x02, timer_presets[0])?;
+        self.write_raw(0x03, timer_presets[1])?;
+        self.set_timer_control(timer_control)?;
+
+        // The tone is written raw, so the overrides of the channel stay valid for its restored registers
+        let channel = Self::melody_channel(0)?;
+        let original_instrument = self.read_instrument_bytes(channel)?;
+        let original_frequency = [self.read_raw(0xA0)?, self.read_raw(0xB0)?];
+        let note = Note::A(4);
+        let frequency_high = ((note.get_frequency() >> 8) as u8) | (note.get_octave() << 2);
+
+        self.write_instrument(channel, &crate::instrument::presets::ELPIANO1)?;
+        self.write_raw(0xA0, note.get_frequency() as u8)?;
+        self.write_raw(0xB0, frequency_high | 0x20)?;
+        delay.delay_ms(200);
+        self.write_raw(0xB0, frequency_high)?;
+        self.write_instrument(channel, &MelodyInstrument::from_bytes(original_instrument))?;
+        self.write_raw(0xA0, original_frequency[0])?;
+        self.write_raw(0xB0, original_frequency[1])?;
+
+        Ok(report)
+    }
+
+    #[cfg(feature = "rhythm")]
+    fn plan_operator_settings<const N: usize>(
+        planner: &mut WritePlanner<N>,
+        channel: ChannelId,
+        operator: Operator,
+        settings: OperatorSettings,
+    ) {
+        let operator = operator_register_index(channel, operator);
+
+        for (base, value) in [0x20, 0x40, 0x60, 0x80, 0xE0]
+            .iter()
+            .zip(settings.to_bytes().iter())
+        {
+            planner.push(base + operator as u8, *value);
+        }
+    }
+
+    #[cfg(feature = "rhythm")]
     fn set_operator_settings(
         &mut self,
-        channel: usize,
-        operator: usize,
+        channel: ChannelId,
+        operator: Operator,
         settings: OperatorSettings,
     ) -> Result<(), Opl2Error> {
-        let operator = match (operator, Self::OPERATOR_MAP[channel]) {
-            (0, (operator, _)) => operator,
-            (1, (_, operator)) => operator,
-            _ => unreachable!(),
-        };
+        self.forget_channel_overrides(channel);
 
-        self.ll()
-            .operator_settings0()
-            .write_index(operator, |_| settings.operator_settings0)?;
-        self.ll()
-            .operator_settings1()
-            .write_index(operator, |_| settings.operator_settings1)?;
-        self.ll()
-            .operator_settings2()
-            .write_index(operator, |_| settings.operator_settings2)?;
-        self.ll()
-            .operator_settings3()
-            .write_index(operator, |_| settings.operator_settings3)?;
-        self.ll()
-            .operator_settings4()
-            .write_index(operator, |_| settings.operator_settings4)?;
+        let mut planner = WritePlanner::<5>::new();
+        Self::plan_operator_settings(&mut planner, channel, operator, settings);
+        planner.execute(self.ll.interface())?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rhythm")]
+    fn set_two_operator_settings(
+        &mut self,
+        channel: ChannelId,
+        operator_0: OperatorSettings,
+        channel_settings2: channel_settings2::W,
+        operator_1: OperatorSettings,
+    ) -> Result<(), Opl2Error> {
+        self.forget_channel_overrides(channel);
+
+        let mut planner = WritePlanner::<11>::new();
+        Self::plan_operator_settings(&mut planner, channel, Operator::Modulator, operator_0);
+        Self::plan_operator_settings(&mut planner, channel, Operator::Carrier, operator_1);
+        planner.push(
+            0xC0 + channel.index() as u8,
+            channel_settings2_to_byte(channel_settings2),
+        );
+        planner.execute(self.ll.interface())?;
 
         Ok(())
     }
@@ -162,43 +1026,236 @@ impl<I: ll::HardwareInterface, INIT: Initialized> Opl2<I, INIT> {
         channel: usize,
         value: MelodyInstrument,
     ) -> Result<(), Opl2Error> {
-        if channel > INIT::CHANNEL_COUNT {
-            return Err(Opl2Error::InvalidChannel);
+        let channel = Self::melody_channel(channel)?;
+        self.forget_channel_overrides(channel);
+        trace!("instrument loaded on channel {}", channel.index());
+
+        self.write_instrument(channel, &value)
+    }
+
+    /// Writes all registers of the instrument as one batch, without touching the overrides of the channel
+    fn write_instrument(
+        &mut self,
+        channel: ChannelId,
+        instrument: &MelodyInstrument,
+    ) -> Result<(), Opl2Error> {
+        let mut planner = WritePlanner::<11>::new();
+        for (address, value) in instrument.register_writes(channel) {
+            planner.push(address, value);
         }
 
-        self.set_operator_settings(channel, 0, value.operator_0)?;
-        self.ll()
-            .channel_settings2()
-            .write_index(channel, |_| value.channel_settings2)?;
-        self.set_operator_settings(channel, 1, value.operator_1)?;
+        planner.execute(self.ll.interface())?;
 
         Ok(())
     }
 
-    pub fn start_channel(&mut self, channel: usize, note: Note) -> Result<(), Opl2Error> {
-        if channel > INIT::CHANNEL_COUNT {
-            return Err(Opl2Error::InvalidChannel);
+    /// Loads the instrument on the channel and starts the note.
+    ///
+    /// Only the instrument registers that differ from what the channel already has are written,
+    /// so playing the instrument that is already loaded only takes the 2 frequency and key-on writes.
+    /// The comparison is done against the register image of the interface and doesn't touch the bus.
+    /// The worst case is [NOTE_ON_MAX_WRITES] writes.
+    /// With the [ll::ShiftInterface] every write has 29 µs of delays plus two 1-byte SPI transfers,
+    /// which bounds a note-on to 13 * 29 = 377 µs plus the SPI time.
+    pub fn play_note(
+        &mut self,
+        channel: usize,
+        instrument: &MelodyInstrument,
+        note: Note,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+        // The instrument brings its own release rate and level
+        self.forget_channel_overrides(channel_id);
+
+        let mut planner = WritePlanner::<11>::new();
+        for (address, value) in instrument.register_writes(channel_id) {
+            if self.read_raw(address)? != value {
+                planner.push(address, value);
+            }
         }
+        planner.execute(self.ll.interface())?;
+        trace!("instrument loaded on channel {} for a note", channel);
 
-        let frequency = note.get_frequency();
-        let octave = note.get_octave();
+        self.start_channel(channel, note)
+    }
+
+    pub fn start_channel(&mut self, channel: usize, note: Note) -> Result<(), Opl2Error> {
+        self.start_channel_frequency(channel, note.get_frequency(), note.get_octave())
+    }
+
+    /// Starts the channel on a raw frequency number (0-1023) and block (0-7).
+    ///
+    /// This can play any frequency, see [crate::tuning::ChipClock::frequency_number].
+    /// Calling it again while the channel plays only changes the pitch.
+    pub fn start_channel_frequency(
+        &mut self,
+        channel: usize,
+        frequency_number: u16,
+        block: u8,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+        trace!(
+            "note on: channel {}, frequency number {}, block {}",
+            channel,
+            frequency_number,
+            block
+        );
+
+        if let Some(patch_value) = self.release_overrides[channel].take() {
+            self.write_raw(Self::carrier_release_address(channel_id), patch_value)?;
+        }
 
         self.ll().channel_settings0().write_index(channel, |w| {
-            w.frequency_number_low((frequency & 0xFF) as u8)
+            w.frequency_number_low((frequency_number & 0xFF) as u8)
         })?;
         self.ll().channel_settings1().write_index(channel, |w| {
-            w.frequency_number_high(((frequency & 0x300) >> 8) as u8)
-                .block_number(octave)
+            w.frequency_number_high(((frequency_number & 0x300) >> 8) as u8)
+                .block_number(block)
                 .key_on(Bit::Set)
         })?;
 
         Ok(())
     }
 
-    pub fn stop_channel(&mut self, channel: usize) -> Result<(), Opl2Error> {
-        if channel > INIT::CHANNEL_COUNT {
-            return Err(Opl2Error::InvalidChannel);
+    /// Changes the frequency number (0-1023) and block (0-7) of the channel without starting or stopping it
+    pub fn set_channel_frequency(
+        &mut self,
+        channel: usize,
+        frequency_number: u16,
+        block: u8,
+    ) -> Result<(), Opl2Error> {
+        Self::melody_channel(channel)?;
+
+        self.ll().channel_settings0().write_index(channel, |w| {
+            w.frequency_number_low((frequency_number & 0xFF) as u8)
+        })?;
+        self.ll()
+            .channel_settings1()
+            .modify_index(channel, |_, w| {
+                w.frequency_number_high(((frequency_number & 0x300) >> 8) as u8)
+                    .block_number(block)
+            })?;
+
+        Ok(())
+    }
+
+    /// The frequency number and block the channel is set to, see [FnumBlock::pitch] for its frequency
+    pub fn channel_frequency(&mut self, channel: usize) -> Result<FnumBlock, Opl2Error> {
+        let channel = ChannelId::new(channel).ok_or(Opl2Error::InvalidChannel)?;
+        let index = channel.index() as u8;
+
+        let high = self.read_raw(0xB0 + index)?;
+        let low = self.read_raw(0xA0 + index)?;
+        Ok(FnumBlock::new(
+            ((high as u16 & 0x03) << 8) | low as u16,
+            (high >> 2) & 0x07,
+        ))
+    }
+
+    /// Sets the volume of the channel from 0 (48 dB softer) to 63 (the level of the instrument) in steps of 0.75 dB.
+    ///
+    /// Only the operators that are heard are attenuated: the carrier for FM and both operators for
+    /// additive synthesis, so the sound of the instrument stays the same.
+    /// The levels of the instrument come back when a new instrument is set up on the channel,
+    /// so the volume must be set after that.
+    pub fn set_channel_volume(&mut self, channel: usize, volume: u8) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+
+        let (modulator, carrier) = match self.level_overrides[channel] {
+            Some(patch_values) => patch_values,
+            None => (
+                self.read_level(channel_id, Operator::Modulator)?,
+                self.read_level(channel_id, Operator::Carrier)?,
+            ),
+        };
+        self.level_overrides[channel] = Some((modulator, carrier));
+
+        let attenuation = 63 - volume.min(63);
+        let attenuate = |level: u8| (level & 0xC0) | ((level & 0x3F) + attenuation).min(0x3F);
+        let additive = self.read_raw(0xC0 + channel_id.index() as u8)? & 0x01 != 0;
+
+        if additive {
+            self.write_level(channel_id, Operator::Modulator, attenuate(modulator))?;
+        }
+        self.write_level(channel_id, Operator::Carrier, attenuate(carrier))?;
+
+        Ok(())
+    }
+
+    /// Silences the operator, or gives it its level back.
+    ///
+    /// Meant for designing instruments, to hear what the carrier and the modulator each add to the sound.
+    /// A muted modulator doesn't modulate the carrier anymore. Volume changes of a muted operator
+    /// are kept until it's unmuted. Setting up a new instrument on the channel unmutes it.
+    pub fn mute_operator(
+        &mut self,
+        channel: usize,
+        operator: Operator,
+        muted: bool,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+        let address = 0x40 + operator_register_index(channel_id, operator) as u8;
+
+        match (muted, self.muted_levels[channel][operator as usize]) {
+            (true, None) => {
+                let level = self.read_raw(address)?;
+                self.write_raw(address, level | 0x3F)?;
+                self.muted_levels[channel][operator as usize] = Some(level);
+            }
+            (false, Some(level)) => {
+                self.write_raw(address, level)?;
+                self.muted_levels[channel][operator as usize] = None;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reads the level register of the operator, as it is when the operator isn't muted
+    fn read_level(&mut self, channel: ChannelId, operator: Operator) -> Result<u8, Opl2Error> {
+        match self.muted_levels[channel.index()][operator as usize] {
+            Some(level) => Ok(level),
+            None => self.read_raw(0x40 + operator_register_index(channel, operator) as u8),
+        }
+    }
+
+    /// Reads the level register of the operator as the instrument has it, without the volume and the mute
+    fn read_patch_level(
+        &mut self,
+        channel: ChannelId,
+        operator: Operator,
+    ) -> Result<u8, Opl2Error> {
+        match (self.level_overrides[channel.index()], operator) {
+            (Some((modulator, _)), Operator::Modulator) => Ok(modulator),
+            (Some((_, carrier)), Operator::Carrier) => Ok(carrier),
+            (None, _) => self.read_level(channel, operator),
         }
+    }
+
+    /// Writes the level register of the operator, or keeps the value for later if the operator is muted
+    fn write_level(
+        &mut self,
+        channel: ChannelId,
+        operator: Operator,
+        value: u8,
+    ) -> Result<(), Opl2Error> {
+        match &mut self.muted_levels[channel.index()][operator as usize] {
+            Some(level) => *level = value,
+            None => {
+                self.write_raw(
+                    0x40 + operator_register_index(channel, operator) as u8,
+                    value,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn stop_channel(&mut self, channel: usize) -> Result<(), Opl2Error> {
+        Self::melody_channel(channel)?;
+        trace!("note off: channel {}", channel);
 
         self.ll()
             .channel_settings1()
@@ -206,9 +1263,210 @@ impl<I: ll::HardwareInterface, INIT: Initialized> Opl2<I, INIT> {
 
         Ok(())
     }
+
+    /// Stops the channel with a different release rate (0-15) than the instrument has.
+    ///
+    /// The release rate of the instrument comes back at the next start of the channel.
+    pub fn stop_channel_with_release(
+        &mut self,
+        channel: usize,
+        release_rate: u8,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+        let address = Self::carrier_release_address(channel_id);
+
+        let current = self.read_raw(address)?;
+        let patch_value = *self.release_overrides[channel].get_or_insert(current);
+        self.write_raw(address, (patch_value & 0xF0) | release_rate.min(0x0F))?;
+
+        self.stop_channel(channel)
+    }
+
+    /// Stops the channel with a MIDI note-off velocity (0-127).
+    ///
+    /// The default velocity of 64 keeps the release of the instrument.
+    /// Every 8 steps above it make the release rate one faster for a staccato stop
+    /// and every 8 steps below it make it one slower to let the note ring.
+    /// The release never becomes 0, because then the note would never stop.
+    pub fn stop_channel_with_velocity(
+        &mut self,
+        channel: usize,
+        velocity: u8,
+    ) -> Result<(), Opl2Error> {
+        let channel_id = Self::melody_channel(channel)?;
+
+        let patch_value = match self.release_overrides[channel] {
+            Some(patch_value) => patch_value,
+            None => self.read_raw(Self::carrier_release_address(channel_id))?,
+        };
+        let offset = (velocity.min(127) as i8 - 64) / 8;
+        let release_rate = ((patch_value & 0x0F) as i8 + offset).clamp(1, 15);
+
+        self.stop_channel_with_release(channel, release_rate as u8)
+    }
+
+    /// The address of the register with the release rate of the carrier
+    fn carrier_release_address(channel: ChannelId) -> u8 {
+        0x80 + operator_register_index(channel, Operator::Carrier) as u8
+    }
+
+    /// Loads the preset value into the timer and starts it. The other timer keeps running.
+    ///
+    /// See [Timer::preset_for_period] to get the preset for a period.
+    pub fn start_timer(&mut self, timer: Timer, preset: u8) -> Result<(), Opl2Error> {
+        match timer {
+            Timer::Timer1 => self.ll().timer1_count().write(|w| w.preset_value(preset))?,
+            Timer::Timer2 => self.ll().timer2_count().write(|w| w.preset_value(preset))?,
+        }
+
+        self.set_timer_control(self.timer_control | timer.start_bit())
+    }
+
+    pub fn stop_timer(&mut self, timer: Timer) -> Result<(), Opl2Error> {
+        self.set_timer_control(self.timer_control & !timer.start_bit())
+    }
+
+    /// When masked, an overflow of the timer doesn't set its flag in the status register or raise the IRQ.
+    ///
+    /// A masked timer keeps counting.
+    pub fn mask_timer(&mut self, timer: Timer, masked: bool) -> Result<(), Opl2Error> {
+        if masked {
+            self.set_timer_control(self.timer_control | timer.mask_bit())
+        } else {
+            self.set_timer_control(self.timer_control & !timer.mask_bit())
+        }
+    }
+
+    /// Clears the IRQ and both timer flags in the status register. The timers keep running.
+    ///
+    /// The chip ignores the other bits of the register while the IRQ reset bit is set,
+    /// so this doesn't change the start and mask bits that the driver keeps.
+    pub fn clear_irq(&mut self) -> Result<(), Opl2Error> {
+        self.ll().timer_control().write(|w| w.irq_reset(Bit::Set))?;
+        Ok(())
+    }
+
+    /// Writes the start and mask bits of the timers, with the IRQ reset bit cleared
+    fn set_timer_control(&mut self, value: u8) -> Result<(), Opl2Error> {
+        self.timer_control = value & 0x63;
+        self.write_raw(0x04, self.timer_control)
+    }
+}
+
+impl<I: ll::StatusInterface, INIT: Initialized> Opl2<I, INIT> {
+    pub fn read_status(&mut self) -> Result<Status, Opl2Error> {
+        Ok(Status::from_byte(self.ll.interface().read_status()?))
+    }
+
+    /// Reads the status register and clears the flags that are set, so the next overflow can be seen.
+    ///
+    /// The returned status has the flags from before they were cleared.
+    pub fn poll_status(&mut self) -> Result<Status, Opl2Error> {
+        let status = self.read_status()?;
+        if status.irq {
+            self.clear_irq()?;
+        }
+        Ok(status)
+    }
+
+    /// Waits until the timer overflows, checking the status register every step of the timer.
+    ///
+    /// Returns false if the timer didn't overflow within the timeout.
+    /// The status is read with [Opl2::poll_status], so an overflow of the other timer is cleared too.
+    pub fn wait_for_timer<D: DelayUs<u16>>(
+        &mut self,
+        timer: Timer,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<bool, Opl2Error> {
+        let step = timer.resolution_us();
+        let mut waited = 0;
+
+        loop {
+            let status = self.poll_status()?;
+            let overflowed = match timer {
+                Timer::Timer1 => status.timer1_overflow,
+                Timer::Timer2 => status.timer2_overflow,
+            };
+
+            if overflowed {
+                return Ok(true);
+            }
+            if waited >= timeout_us {
+                return Ok(false);
+            }
+
+            delay.delay_us(step as u16);
+            waited += step;
+        }
+    }
+
+    /// Finds out which chip is on the bus with the detection sequence of the AdLib drivers.
+    ///
+    /// Timer 1 is started with the shortest period and the status register must show its flag afterwards.
+    /// An OPL3 is recognized because the unused bits of its status register read as 0, where an OPL2 has bits 1 and 2 set.
+    /// The OPL can't be told apart from the OPL2: it only lacks the waveform select, which has no effect
+    /// that can be read back, so it's reported as an OPL2.
+    ///
+    /// Returns `None` if no chip answered. Both timers are stopped and their flags cleared afterwards.
+    pub fn detect_chip<D: DelayUs<u16>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Option<ChipKind>, Opl2Error> {
+        let masked = Timer::Timer1.mask_bit() | Timer::Timer2.mask_bit();
+
+        self.set_timer_control(masked)?;
+        self.clear_irq()?;
+        let before = self.ll.interface().read_status()?;
+
+        self.ll().timer1_count().write(|w| w.preset_value(0xFF))?;
+        self.set_timer_control(Timer::Timer2.mask_bit() | Timer::Timer1.start_bit())?;
+        // One step of timer 1 is 80 µs
+        delay.delay_us(100);
+        let after = self.ll.interface().read_status()?;
+
+        self.set_timer_control(masked)?;
+        self.clear_irq()?;
+
+        if before & 0xE0 != 0x00 || after & 0xE0 != 0xC0 {
+            return Ok(None);
+        }
+
+        if before & 0x06 == 0 {
+            Ok(Some(ChipKind::Opl3))
+        } else {
+            Ok(Some(ChipKind::Opl2))
+        }
+    }
+
+    /// The [Opl2::self_test] that also checks that both timers raise their overflow flag
+    pub fn self_test_with_status<D: DelayMs<u8>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<SelfTestReport, Opl2Error> {
+        self.run_self_test(delay, |opl| opl.read_status().map(Some))
+    }
 }
 
 impl<I: ll::HardwareInterface> Opl2<I, Melody> {
+    /// Resets the chip and clears all registers again, like [Opl2::initialize] does.
+    ///
+    /// Brings back a chip that may have lost its state, for example after communication errors or a power glitch.
+    /// The volumes, mutes and release overrides of the channels are forgotten.
+    pub fn reinitialize(&mut self) -> Result<(), Opl2Error> {
+        self.reinitialize_with(InitConfig::default())
+    }
+
+    /// Resets the chip again with the given config, like [Opl2::initialize_with] does
+    pub fn reinitialize_with(&mut self, config: InitConfig) -> Result<(), Opl2Error> {
+        self.reset_progress = None;
+        self.finish_reset(config)?;
+        trace!("reinitialized");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rhythm")]
     pub fn into_rhythm_mode(mut self) -> Result<Opl2<I, Rhythm>, Opl2Error> {
         // KEY-ON registers for channels 06, 07, and 08 must be OFF in order to use the rhythm section.
         for i in 6..=8 {
@@ -220,21 +1478,36 @@ impl<I: ll::HardwareInterface> Opl2<I, Melody> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.instrument_mode(InstrumentMode::Percussion))?;
+        trace!("into rhythm mode");
 
         Ok(Opl2 {
             ll: self.ll,
+            reset_progress: self.reset_progress,
+            refresh_cursor: self.refresh_cursor,
+            release_overrides: self.release_overrides,
+            level_overrides: self.level_overrides,
+            muted_levels: self.muted_levels,
+            timer_control: self.timer_control,
             phantom: PhantomData::default(),
         })
     }
 }
 
+#[cfg(feature = "rhythm")]
 impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
     pub fn into_melody_mode(mut self) -> Result<Opl2<I, Melody>, Opl2Error> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.instrument_mode(InstrumentMode::Melodic))?;
+        trace!("into melody mode");
         Ok(Opl2 {
             ll: self.ll,
+            reset_progress: self.reset_progress,
+            refresh_cursor: self.refresh_cursor,
+            release_overrides: self.release_overrides,
+            level_overrides: self.level_overrides,
+            muted_levels: self.muted_levels,
+            timer_control: self.timer_control,
             phantom: PhantomData::default(),
         })
     }
@@ -243,16 +1516,18 @@ impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.bass_drum_on(value.into()))?;
+        trace!("bass drum on: {}", value);
         Ok(())
     }
 
     pub fn setup_bass_drum(&mut self, value: BassDrum) -> Result<(), Opl2Error> {
-        self.set_operator_settings(BassDrum::CHANNEL, 0, value.operator_0)?;
-        self.ll()
-            .channel_settings2()
-            .write_index(BassDrum::CHANNEL, |_| value.channel_settings2)?;
-        self.set_operator_settings(BassDrum::CHANNEL, 1, value.operator_1)?;
-
+        self.set_two_operator_settings(
+            BassDrum::CHANNEL,
+            value.operator_0,
+            value.channel_settings2,
+            value.operator_1,
+        )?;
+        trace!("bass drum set up");
         Ok(())
     }
 
@@ -260,11 +1535,13 @@ impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.snare_drum_on(value.into()))?;
+        trace!("snare drum on: {}", value);
         Ok(())
     }
 
     pub fn setup_snare_drum(&mut self, value: SnareDrum) -> Result<(), Opl2Error> {
         self.set_operator_settings(SnareDrum::CHANNEL, SnareDrum::OPERATOR, value.operator)?;
+        trace!("snare drum set up");
 
         Ok(())
     }
@@ -273,11 +1550,13 @@ impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.tom_tom_on(value.into()))?;
+        trace!("tom-tom on: {}", value);
         Ok(())
     }
 
     pub fn setup_tom_tom(&mut self, value: TomTom) -> Result<(), Opl2Error> {
         self.set_operator_settings(TomTom::CHANNEL, TomTom::OPERATOR, value.operator)?;
+        trace!("tom-tom set up");
 
         Ok(())
     }
@@ -286,11 +1565,13 @@ impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.cymbal_on(value.into()))?;
+        trace!("cymbal on: {}", value);
         Ok(())
     }
 
     pub fn setup_cymbal(&mut self, value: Cymbal) -> Result<(), Opl2Error> {
         self.set_operator_settings(Cymbal::CHANNEL, Cymbal::OPERATOR, value.operator)?;
+        trace!("cymbal set up");
 
         Ok(())
     }
@@ -299,12 +1580,106 @@ impl<I: ll::HardwareInterface> Opl2<I, Rhythm> {
         self.ll()
             .rhythm_settings()
             .modify(|_, w| w.hi_hat_on(value.into()))?;
+        trace!("hi-hat on: {}", value);
         Ok(())
     }
 
     pub fn setup_hi_hat(&mut self, value: HiHat) -> Result<(), Opl2Error> {
         self.set_operator_settings(HiHat::CHANNEL, HiHat::OPERATOR, value.operator)?;
+        trace!("hi-hat set up");
+
+        Ok(())
+    }
 
+    /// Sets up all five drums at once
+    pub fn setup_drum_kit(&mut self, value: DrumKit) -> Result<(), Opl2Error> {
+        self.setup_bass_drum(value.bass_drum)?;
+        self.setup_snare_drum(value.snare_drum)?;
+        self.setup_tom_tom(value.tom_tom)?;
+        self.setup_cymbal(value.cymbal)?;
+        self.setup_hi_hat(value.hi_hat)?;
+        trace!("drum kit set up");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::presets;
+    use crate::testing::MockInterface;
+
+    #[test]
+    fn play_note_stays_within_the_write_bound() {
+        let mut opl = Opl2::new(MockInterface::<32>::new()).initialize().unwrap();
+
+        // Every instrument register differs from the one before, which is the worst case
+        let instruments = [
+            presets::ELPIANO1,
+            MelodyInstrument::from_bytes([0x11; 11]),
+            MelodyInstrument::from_bytes([0x22; 11]),
+            presets::STRINGS1,
+        ];
+        for instrument in instruments.iter() {
+            opl.write_log().clear_log();
+            opl.play_note(0, instrument, Note::A(4)).unwrap();
+            assert!(opl.write_log().write_count() <= NOTE_ON_MAX_WRITES);
+        }
+
+        opl.write_log().clear_log();
+        opl.play_note(0, &MelodyInstrument::from_bytes([0x33; 11]), Note::C(3))
+            .unwrap();
+        assert_eq!(opl.write_log().write_count(), NOTE_ON_MAX_WRITES);
+    }
+
+    #[test]
+    fn play_note_skips_the_loaded_instrument() {
+        let mut opl = Opl2::new(MockInterface::<32>::new()).initialize().unwrap();
+
+        opl.play_note(3, &presets::GUITAR1, Note::E(3)).unwrap();
+        opl.stop_channel(3).unwrap();
+        opl.write_log().clear_log();
+        opl.play_note(3, &presets::GUITAR1, Note::G(3)).unwrap();
+
+        let writes = opl.write_log();
+        assert_eq!(writes.write_count(), 2);
+        assert!(writes
+            .register_writes()
+            .all(|(address, _)| address == 0xA3 || address == 0xB3));
+    }
+
+    #[test]
+    fn patch_bytes_leave_out_the_overrides() {
+        let mut opl = Opl2::new(MockInterface::<32>::new()).initialize().unwrap();
+        let channel = ChannelId::new(2).unwrap();
+
+        opl.setup_melody_instrument(2, presets::ELPIANO1).unwrap();
+        let patch = opl.read_instrument_bytes(channel).unwrap();
+
+        opl.set_channel_volume(2, 20).unwrap();
+        opl.mute_operator(2, Operator::Modulator, true).unwrap();
+        opl.stop_channel_with_release(2, 15).unwrap();
+        assert_ne!(opl.read_instrument_bytes(channel).unwrap(), patch);
+        assert_eq!(opl.read_patch_bytes(channel).unwrap(), patch);
+    }
+
+    #[test]
+    fn patch_writes_keep_the_overrides_working() {
+        let mut opl = Opl2::new(MockInterface::<32>::new()).initialize().unwrap();
+        let channel = ChannelId::new(0).unwrap();
+
+        opl.setup_melody_instrument(0, presets::ELPIANO1).unwrap();
+        opl.mute_operator(0, Operator::Carrier, true).unwrap();
+        opl.write_patch_raw(channel, 0x43, 0x12).unwrap();
+        assert_eq!(opl.read_raw(0x43).unwrap() & 0x3F, 0x3F);
+        assert_eq!(opl.read_patch_raw(channel, 0x43).unwrap(), 0x12);
+        opl.mute_operator(0, Operator::Carrier, false).unwrap();
+        assert_eq!(opl.read_raw(0x43).unwrap(), 0x12);
+
+        opl.stop_channel_with_release(0, 15).unwrap();
+        opl.write_patch_raw(channel, 0x83, 0x34).unwrap();
+        assert_eq!(opl.read_raw(0x83).unwrap(), 0x3F);
+        opl.start_channel(0, Note::C(4)).unwrap();
+        assert_eq!(opl.read_raw(0x83).unwrap(), 0x34);
+    }
+}
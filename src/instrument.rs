@@ -4,6 +4,14 @@ use crate::ll::registers::operator_settings1;
 use crate::ll::registers::operator_settings2;
 use crate::ll::registers::operator_settings3;
 use crate::ll::registers::operator_settings4;
+use crate::ll::{operator_register_index, ChannelId, MemoryInterface, Operator, Opl2LL};
+use device_driver::ll::LowLevelDevice;
+
+mod bank;
+pub(crate) mod ibk;
+mod op2;
+pub use ibk::InstrumentBank;
+pub use op2::{Op2Bank, Op2Instrument, Op2Voice};
 
 #[derive(Debug, Copy, Clone)]
 pub struct OperatorSettings {
@@ -40,6 +48,60 @@ impl OperatorSettings {
             operator_settings4::W::from_raw([bytes[4]]),
         )
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let mut ll = Opl2LL::new(MemoryInterface::new());
+
+        // Writing to the memory interface can't fail
+        let mut registers = ll.registers();
+        let _ = registers
+            .operator_settings0()
+            .write_index(0, |_| self.operator_settings0);
+        let _ = registers
+            .operator_settings1()
+            .write_index(0, |_| self.operator_settings1);
+        let _ = registers
+            .operator_settings2()
+            .write_index(0, |_| self.operator_settings2);
+        let _ = registers
+            .operator_settings3()
+            .write_index(0, |_| self.operator_settings3);
+        let _ = registers
+            .operator_settings4()
+            .write_index(0, |_| self.operator_settings4);
+
+        let image = ll.interface().registers();
+        [
+            image[0x20],
+            image[0x40],
+            image[0x60],
+            image[0x80],
+            image[0xE0],
+        ]
+    }
+}
+
+/// Get the raw register value of the channel settings
+pub(crate) fn channel_settings2_to_byte(channel_settings2: channel_settings2::W) -> u8 {
+    let mut ll = Opl2LL::new(MemoryInterface::new());
+
+    // Writing to the memory interface can't fail
+    let _ = ll
+        .registers()
+        .channel_settings2()
+        .write_index(0, |_| channel_settings2);
+
+    ll.interface().registers()[0xC0]
+}
+
+/// Build an 11 byte instrument out of its parts in the order that the `from_bytes` functions take them
+fn instrument_bytes(operator_0: [u8; 5], channel_settings2: u8, operator_1: [u8; 5]) -> [u8; 11] {
+    let mut bytes = [0; 11];
+    bytes[0..5].copy_from_slice(&operator_0);
+    bytes[5] = channel_settings2;
+    bytes[6..11].copy_from_slice(&operator_1);
+    bytes
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -68,16 +130,183 @@ impl MelodyInstrument {
             OperatorSettings::from_bytes([bytes[6], bytes[7], bytes[8], bytes[9], bytes[10]]),
         )
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 11] {
+        instrument_bytes(
+            self.operator_0.to_bytes(),
+            channel_settings2_to_byte(self.channel_settings2),
+            self.operator_1.to_bytes(),
+        )
+    }
+
+    /// All register writes that put this instrument on the channel as (address, value) pairs.
+    ///
+    /// The writes come in the order of [Self::from_bytes].
+    /// Can be used to export the instrument into a register log like DRO or VGM.
+    pub fn register_writes(&self, channel: ChannelId) -> impl Iterator<Item = (u8, u8)> {
+        const BASE_ADDRESSES: [u8; 11] = [
+            0x20, 0x40, 0x60, 0x80, 0xE0, 0xC0, 0x20, 0x40, 0x60, 0x80, 0xE0,
+        ];
+
+        let modulator = operator_register_index(channel, Operator::Modulator) as u8;
+        let carrier = operator_register_index(channel, Operator::Carrier) as u8;
+        let bytes = self.to_bytes();
+
+        (0..bytes.len()).map(move |i| {
+            let offset = match i {
+                0..=4 => modulator,
+                5 => channel.index() as u8,
+                _ => carrier,
+            };
+            (BASE_ADDRESSES[i] + offset, bytes[i])
+        })
+    }
+}
+
+/// An OPL3 4-operator instrument.
+///
+/// The OPL3 combines two 2-op channels into one 4-op voice.
+/// Each half has the same layout as a [MelodyInstrument] and the synthesis type bits of both halves
+/// together select how the four operators are connected:
+///
+/// | First half | Second half | Connection |
+/// |---|---|---|
+/// | FM | FM | `1 -> 2 -> 3 -> 4` |
+/// | AM | FM | `1 + (2 -> 3 -> 4)` |
+/// | FM | AM | `(1 -> 2) + (3 -> 4)` |
+/// | AM | AM | `1 + (2 -> 3) + 4` |
+///
+/// The driver can't play these yet. Pairing the channels takes the 4-op connection select register (0x104),
+/// which is in the second register bank of the OPL3 that the interfaces can't address.
+/// Until then the type is for storing and converting instruments, see [Self::approximate_melody_instrument].
+#[derive(Debug, Copy, Clone)]
+pub struct FourOpInstrument {
+    pub first_half: MelodyInstrument,
+    pub second_half: MelodyInstrument,
+}
+impl FourOpInstrument {
+    pub const fn new(first_half: MelodyInstrument, second_half: MelodyInstrument) -> Self {
+        Self {
+            first_half,
+            second_half,
+        }
+    }
+
+    pub const fn from_bytes(bytes: [u8; 22]) -> Self {
+        Self::new(
+            MelodyInstrument::from_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+                bytes[8], bytes[9], bytes[10],
+            ]),
+            MelodyInstrument::from_bytes([
+                bytes[11], bytes[12], bytes[13], bytes[14], bytes[15], bytes[16], bytes[17],
+                bytes[18], bytes[19], bytes[20], bytes[21],
+            ]),
+        )
+    }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 22] {
+        let mut bytes = [0; 22];
+        bytes[0..11].copy_from_slice(&self.first_half.to_bytes());
+        bytes[11..22].copy_from_slice(&self.second_half.to_bytes());
+        bytes
+    }
+
+    /// Creates a 4-op instrument that sounds the same as the 2-op instrument.
+    ///
+    /// A FM instrument becomes the first half of a `(1 -> 2) + (3 -> 4)` connection with a silent second half.
+    /// An additive instrument is split over operators 1 and 4 of a `1 + (2 -> 3) + 4` connection.
+    pub fn from_melody_instrument(instrument: MelodyInstrument) -> Self {
+        const SILENT_OPERATOR: [u8; 5] = [0x00, 0x3F, 0x00, 0x00, 0x00];
+
+        let bytes = instrument.to_bytes();
+        let (operator_a, channel_settings2, operator_b) = (
+            [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]],
+            bytes[5],
+            [bytes[6], bytes[7], bytes[8], bytes[9], bytes[10]],
+        );
+
+        let (first_half, second_half) = if channel_settings2 & 0x01 == 0 {
+            (
+                bytes,
+                instrument_bytes(SILENT_OPERATOR, 0x01, SILENT_OPERATOR),
+            )
+        } else {
+            (
+                instrument_bytes(operator_a, channel_settings2, SILENT_OPERATOR),
+                instrument_bytes(SILENT_OPERATOR, 0x01, operator_b),
+            )
+        };
+
+        Self::new(
+            MelodyInstrument::from_bytes(first_half),
+            MelodyInstrument::from_bytes(second_half),
+        )
+    }
+
+    /// Approximates this instrument with a 2-op instrument so it can be played on an OPL2.
+    ///
+    /// This is lossy. Only the two operators that contribute most to the sound are kept:
+    ///
+    /// - `1 -> 2 -> 3 -> 4`: operators 3 and 4 as FM. The modulation of operator 3 by 1 and 2 is lost.
+    /// - `1 + (2 -> 3 -> 4)`: operators 3 and 4 as FM. Operator 1 and the modulation by 2 are lost.
+    /// - `(1 -> 2) + (3 -> 4)`: the pair with the loudest carrier as FM. The other pair is lost.
+    /// - `1 + (2 -> 3) + 4`: operators 1 and 4 as additive. The `2 -> 3` pair is lost.
+    ///
+    /// Feedback only survives when operator 1 is kept, because that is the only operator it applies to.
+    /// The OPL3 output bits of the channel settings are cleared.
+    pub fn approximate_melody_instrument(&self) -> MelodyInstrument {
+        let first = self.first_half.to_bytes();
+        let second = self.second_half.to_bytes();
+
+        let operator = |half: &[u8; 11], index: usize| -> [u8; 5] {
+            let start = if index == 0 { 0 } else { 6 };
+            [
+                half[start],
+                half[start + 1],
+                half[start + 2],
+                half[start + 3],
+                half[start + 4],
+            ]
+        };
+        let output_level = |operator: [u8; 5]| operator[1] & 0x3F;
+        let feedback = first[5] & 0x0E;
+
+        let bytes = match (first[5] & 0x01, second[5] & 0x01) {
+            (_, 0) => instrument_bytes(operator(&second, 0), 0x00, operator(&second, 1)),
+            (0, _) => {
+                // Higher output level is more attenuation
+                if output_level(operator(&first, 1)) <= output_level(operator(&second, 1)) {
+                    instrument_bytes(operator(&first, 0), feedback, operator(&first, 1))
+                } else {
+                    instrument_bytes(operator(&second, 0), 0x00, operator(&second, 1))
+                }
+            }
+            _ => instrument_bytes(operator(&first, 0), feedback | 0x01, operator(&second, 1)),
+        };
+
+        MelodyInstrument::from_bytes(bytes)
+    }
+}
+
+impl From<MelodyInstrument> for FourOpInstrument {
+    fn from(instrument: MelodyInstrument) -> Self {
+        Self::from_melody_instrument(instrument)
+    }
 }
 
+#[cfg(feature = "rhythm")]
 #[derive(Debug, Copy, Clone)]
 pub struct BassDrum {
     pub operator_0: OperatorSettings,
     pub channel_settings2: channel_settings2::W,
     pub operator_1: OperatorSettings,
 }
+#[cfg(feature = "rhythm")]
 impl BassDrum {
-    pub const CHANNEL: usize = 6;
+    pub const CHANNEL: ChannelId = ChannelId::BASS_DRUM;
 
     pub const fn new(
         operator_0: OperatorSettings,
@@ -98,15 +327,26 @@ impl BassDrum {
             OperatorSettings::from_bytes([bytes[6], bytes[7], bytes[8], bytes[9], bytes[10]]),
         )
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 11] {
+        instrument_bytes(
+            self.operator_0.to_bytes(),
+            channel_settings2_to_byte(self.channel_settings2),
+            self.operator_1.to_bytes(),
+        )
+    }
 }
 
+#[cfg(feature = "rhythm")]
 #[derive(Debug, Copy, Clone)]
 pub struct SnareDrum {
     pub operator: OperatorSettings,
 }
+#[cfg(feature = "rhythm")]
 impl SnareDrum {
-    pub const CHANNEL: usize = 7;
-    pub const OPERATOR: usize = 1;
+    pub const CHANNEL: ChannelId = ChannelId::SNARE_DRUM_HI_HAT;
+    pub const OPERATOR: Operator = Operator::Carrier;
 
     pub const fn new(operator: OperatorSettings) -> Self {
         Self { operator }
@@ -117,15 +357,22 @@ impl SnareDrum {
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4],
         ]))
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 5] {
+        self.operator.to_bytes()
+    }
 }
 
+#[cfg(feature = "rhythm")]
 #[derive(Debug, Copy, Clone)]
 pub struct TomTom {
     pub operator: OperatorSettings,
 }
+#[cfg(feature = "rhythm")]
 impl TomTom {
-    pub const CHANNEL: usize = 8;
-    pub const OPERATOR: usize = 0;
+    pub const CHANNEL: ChannelId = ChannelId::TOM_TOM_CYMBAL;
+    pub const OPERATOR: Operator = Operator::Modulator;
 
     pub const fn new(operator: OperatorSettings) -> Self {
         Self { operator }
@@ -136,15 +383,22 @@ impl TomTom {
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4],
         ]))
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 5] {
+        self.operator.to_bytes()
+    }
 }
 
+#[cfg(feature = "rhythm")]
 #[derive(Debug, Copy, Clone)]
 pub struct Cymbal {
     pub operator: OperatorSettings,
 }
+#[cfg(feature = "rhythm")]
 impl Cymbal {
-    pub const CHANNEL: usize = 8;
-    pub const OPERATOR: usize = 1;
+    pub const CHANNEL: ChannelId = ChannelId::TOM_TOM_CYMBAL;
+    pub const OPERATOR: Operator = Operator::Carrier;
 
     pub const fn new(operator: OperatorSettings) -> Self {
         Self { operator }
@@ -155,15 +409,22 @@ impl Cymbal {
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4],
         ]))
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 5] {
+        self.operator.to_bytes()
+    }
 }
 
+#[cfg(feature = "rhythm")]
 #[derive(Debug, Copy, Clone)]
 pub struct HiHat {
     pub operator: OperatorSettings,
 }
+#[cfg(feature = "rhythm")]
 impl HiHat {
-    pub const CHANNEL: usize = 7;
-    pub const OPERATOR: usize = 0;
+    pub const CHANNEL: ChannelId = ChannelId::SNARE_DRUM_HI_HAT;
+    pub const OPERATOR: Operator = Operator::Modulator;
 
     pub const fn new(operator: OperatorSettings) -> Self {
         Self { operator }
@@ -174,38 +435,184 @@ impl HiHat {
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4],
         ]))
     }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 5] {
+        self.operator.to_bytes()
+    }
+}
+
+/// The instruments of all five drums of the percussion mode
+#[cfg(feature = "rhythm")]
+#[derive(Debug, Copy, Clone)]
+pub struct DrumKit {
+    pub bass_drum: BassDrum,
+    pub snare_drum: SnareDrum,
+    pub tom_tom: TomTom,
+    pub cymbal: Cymbal,
+    pub hi_hat: HiHat,
+}
+
+#[cfg(feature = "rhythm")]
+impl DrumKit {
+    /// Takes the bass drum, snare drum, tom-tom, cymbal and hi-hat bytes after each other
+    pub const fn from_bytes(bytes: [u8; 31]) -> Self {
+        let b = bytes;
+        Self {
+            bass_drum: BassDrum::from_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10],
+            ]),
+            snare_drum: SnareDrum::from_bytes([b[11], b[12], b[13], b[14], b[15]]),
+            tom_tom: TomTom::from_bytes([b[16], b[17], b[18], b[19], b[20]]),
+            cymbal: Cymbal::from_bytes([b[21], b[22], b[23], b[24], b[25]]),
+            hi_hat: HiHat::from_bytes([b[26], b[27], b[28], b[29], b[30]]),
+        }
+    }
+
+    /// Get the raw register values in the same order as [Self::from_bytes] takes them
+    pub fn to_bytes(&self) -> [u8; 31] {
+        let mut bytes = [0; 31];
+        bytes[0..11].copy_from_slice(&self.bass_drum.to_bytes());
+        bytes[11..16].copy_from_slice(&self.snare_drum.to_bytes());
+        bytes[16..21].copy_from_slice(&self.tom_tom.to_bytes());
+        bytes[21..26].copy_from_slice(&self.cymbal.to_bytes());
+        bytes[26..31].copy_from_slice(&self.hi_hat.to_bytes());
+        bytes
+    }
+}
+
+/// Serializes the instruments as a format version and their raw register values.
+///
+/// This is about as compact as it gets, so it works well with binary formats like postcard.
+/// The version lets later releases still read what is stored now.
+#[cfg(feature = "serde")]
+mod serialization {
+    use super::*;
+    use serde::de::{Deserialize, Deserializer, Error, Unexpected};
+    use serde::ser::{Serialize, Serializer};
+
+    const FORMAT_VERSION: u8 = 1;
+
+    macro_rules! serialize_as_bytes {
+        ($($instrument:ty: $size:literal),*) => {
+            $(
+                impl Serialize for $instrument {
+                    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                        (FORMAT_VERSION, self.to_bytes()).serialize(serializer)
+                    }
+                }
+
+                impl<'de> Deserialize<'de> for $instrument {
+                    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                        let (version, bytes) = <(u8, [u8; $size])>::deserialize(deserializer)?;
+                        if version != FORMAT_VERSION {
+                            return Err(D::Error::invalid_value(
+                                Unexpected::Unsigned(version as u64),
+                                &"instrument format version 1",
+                            ));
+                        }
+                        Ok(Self::from_bytes(bytes))
+                    }
+                }
+            )*
+        };
+    }
+
+    serialize_as_bytes!(
+        OperatorSettings: 5,
+        MelodyInstrument: 11,
+        FourOpInstrument: 22
+    );
+
+    #[cfg(feature = "rhythm")]
+    serialize_as_bytes!(
+        BassDrum: 11,
+        SnareDrum: 5,
+        TomTom: 5,
+        Cymbal: 5,
+        HiHat: 5,
+        DrumKit: 31
+    );
 }
 
 pub mod presets {
     use super::*;
 
-    pub const ELPIANO1: MelodyInstrument = MelodyInstrument::from_bytes([
+    // Every preset goes through these checks, so bad preset data fails the build
+
+    const fn operator_is_audible(bytes: [u8; 5]) -> bool {
+        let output_level = bytes[1] & 0x3F;
+        let attack_rate = bytes[2] >> 4;
+        output_level < 0x3F && attack_rate > 0
+    }
+
+    #[cfg(feature = "rhythm")]
+    const fn checked_operator(bytes: [u8; 5]) -> [u8; 5] {
+        assert!(bytes[4] <= 0x03, "The OPL2 only has 4 waveforms");
+        assert!(
+            operator_is_audible(bytes),
+            "The operator is fully attenuated or never attacks"
+        );
+        bytes
+    }
+
+    const fn checked_instrument(bytes: [u8; 11]) -> [u8; 11] {
+        let modulator = [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]];
+        let carrier = [bytes[6], bytes[7], bytes[8], bytes[9], bytes[10]];
+        let additive = bytes[5] & 0x01 != 0;
+
+        assert!(
+            bytes[4] <= 0x03 && bytes[10] <= 0x03,
+            "The OPL2 only has 4 waveforms"
+        );
+        assert!(
+            bytes[5] <= 0x0F,
+            "The upper bits of the channel settings don't exist on the OPL2"
+        );
+        assert!(
+            operator_is_audible(carrier) || (additive && operator_is_audible(modulator)),
+            "The instrument is silent"
+        );
+        bytes
+    }
+
+    pub const ELPIANO1: MelodyInstrument = MelodyInstrument::from_bytes(checked_instrument([
         0x01, 0x4F, 0xF1, 0x50, 0x00, 0x06, 0x01, 0x04, 0xD2, 0x7C, 0x00,
-    ]);
-    pub const GUITAR1: MelodyInstrument = MelodyInstrument::from_bytes([
+    ]));
+    pub const GUITAR1: MelodyInstrument = MelodyInstrument::from_bytes(checked_instrument([
         0x01, 0x11, 0xF2, 0x1F, 0x00, 0x0A, 0x01, 0x00, 0xF5, 0x88, 0x00,
-    ]);
-    pub const STRINGS1: MelodyInstrument = MelodyInstrument::from_bytes([
+    ]));
+    pub const STRINGS1: MelodyInstrument = MelodyInstrument::from_bytes(checked_instrument([
         0xB1, 0x8B, 0x71, 0x11, 0x00, 0x06, 0x61, 0x40, 0x42, 0x15, 0x01,
-    ]);
+    ]));
 
+    #[cfg(feature = "rhythm")]
     pub mod drums {
         use super::*;
 
-        pub const BDRUM1: BassDrum = BassDrum::from_bytes([
+        pub const BDRUM1: BassDrum = BassDrum::from_bytes(checked_instrument([
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0B, 0xA8, 0x4C, 0x00,
-        ]);
-        pub const CYMBAL1: Cymbal = Cymbal::from_bytes([0x01, 0x00, 0xF5, 0xB5, 0x00]);
-        pub const HIHAT1: HiHat = HiHat::from_bytes([0x01, 0x00, 0xF7, 0xB5, 0x00]);
-        pub const HIHAT2: HiHat = HiHat::from_bytes([0x01, 0x03, 0xDA, 0x18, 0x00]);
-        pub const LASER: Cymbal = Cymbal::from_bytes([0xE6, 0x00, 0x25, 0xB5, 0x00]);
-        pub const MLTRDRUM: SnareDrum = SnareDrum::from_bytes([0x0C, 0x00, 0xC8, 0xB6, 0x01]);
-        pub const RKSNARE: SnareDrum = SnareDrum::from_bytes([0x0C, 0x00, 0xC7, 0xB4, 0x00]);
-        pub const SNARE1: SnareDrum = SnareDrum::from_bytes([0x0C, 0x00, 0xF8, 0xB5, 0x00]);
-        pub const TOM1: TomTom = TomTom::from_bytes([0x04, 0x00, 0xF7, 0xB5, 0x00]);
-        pub const TOM2: TomTom = TomTom::from_bytes([0x02, 0x00, 0xC8, 0x97, 0x00]);
-        pub const XYLO2: BassDrum = BassDrum::from_bytes([
+        ]));
+        pub const CYMBAL1: Cymbal =
+            Cymbal::from_bytes(checked_operator([0x01, 0x00, 0xF5, 0xB5, 0x00]));
+        pub const HIHAT1: HiHat =
+            HiHat::from_bytes(checked_operator([0x01, 0x00, 0xF7, 0xB5, 0x00]));
+        pub const HIHAT2: HiHat =
+            HiHat::from_bytes(checked_operator([0x01, 0x03, 0xDA, 0x18, 0x00]));
+        pub const LASER: Cymbal =
+            Cymbal::from_bytes(checked_operator([0xE6, 0x00, 0x25, 0xB5, 0x00]));
+        pub const MLTRDRUM: SnareDrum =
+            SnareDrum::from_bytes(checked_operator([0x0C, 0x00, 0xC8, 0xB6, 0x01]));
+        pub const RKSNARE: SnareDrum =
+            SnareDrum::from_bytes(checked_operator([0x0C, 0x00, 0xC7, 0xB4, 0x00]));
+        pub const SNARE1: SnareDrum =
+            SnareDrum::from_bytes(checked_operator([0x0C, 0x00, 0xF8, 0xB5, 0x00]));
+        pub const TOM1: TomTom =
+            TomTom::from_bytes(checked_operator([0x04, 0x00, 0xF7, 0xB5, 0x00]));
+        pub const TOM2: TomTom =
+            TomTom::from_bytes(checked_operator([0x02, 0x00, 0xC8, 0x97, 0x00]));
+        pub const XYLO2: BassDrum = BassDrum::from_bytes(checked_instrument([
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2E, 0x00, 0xFF, 0x0F, 0x00,
-        ]);
+        ]));
     }
 }
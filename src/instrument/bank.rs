@@ -0,0 +1,67 @@
+/// The layout of the bank files that are a magic number, a fixed amount of records and then a fixed size name for every record
+#[derive(Debug)]
+pub(crate) struct BankLayout {
+    pub magic: &'static [u8],
+    /// The amount of records in a bank
+    pub len: usize,
+    pub record_size: usize,
+    /// The size of a name, including its terminating zero
+    pub name_size: usize,
+}
+
+impl BankLayout {
+    pub const fn file_size(&self) -> usize {
+        self.magic.len() + self.len * (self.record_size + self.name_size)
+    }
+
+    /// Returns `None` if the bytes are not a bank of this layout
+    pub fn parse<'a>(&'static self, bytes: &'a [u8]) -> Option<RecordBank<'a>> {
+        if bytes.len() < self.file_size() || !bytes.starts_with(self.magic) {
+            return None;
+        }
+
+        Some(RecordBank {
+            bytes,
+            layout: self,
+        })
+    }
+}
+
+/// The records and names of a bank, read straight from the bytes
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct RecordBank<'a> {
+    bytes: &'a [u8],
+    layout: &'static BankLayout,
+}
+
+impl<'a> RecordBank<'a> {
+    /// The record at the index
+    pub fn record(&self, index: usize) -> Option<&'a [u8]> {
+        (index < self.layout.len).then(|| self.record_at(index))
+    }
+
+    /// The name of the record, or `None` if the index is out of range.
+    ///
+    /// Names that aren't valid UTF-8, like the CP437 names of DOS tools, are an empty string.
+    pub fn name(&self, index: usize) -> Option<&'a str> {
+        (index < self.layout.len).then(|| self.name_at(index))
+    }
+
+    /// The index, name and record of every entry in the order of the bank
+    pub fn iter(self) -> impl Iterator<Item = (usize, &'a str, &'a [u8])> {
+        (0..self.layout.len).map(move |index| (index, self.name_at(index), self.record_at(index)))
+    }
+
+    fn record_at(&self, index: usize) -> &'a [u8] {
+        let start = self.layout.magic.len() + index * self.layout.record_size;
+        &self.bytes[start..start + self.layout.record_size]
+    }
+
+    fn name_at(&self, index: usize) -> &'a str {
+        let layout = self.layout;
+        let start = layout.magic.len() + layout.len * layout.record_size + index * layout.name_size;
+        let name = &self.bytes[start..start + layout.name_size];
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        core::str::from_utf8(&name[..len]).unwrap_or("")
+    }
+}
@@ -0,0 +1,153 @@
+use super::bank::{BankLayout, RecordBank};
+use super::MelodyInstrument;
+
+/// The magic number at the start of an IBK file
+pub(crate) const MAGIC: &[u8; 4] = b"IBK\x1A";
+/// The size of an instrument record
+pub(crate) const RECORD_SIZE: usize = 16;
+/// The size of a name, including its terminating zero
+pub(crate) const NAME_SIZE: usize = 9;
+
+const LAYOUT: BankLayout = BankLayout {
+    magic: MAGIC,
+    len: InstrumentBank::LEN,
+    record_size: RECORD_SIZE,
+    name_size: NAME_SIZE,
+};
+
+/// A bank of 128 instruments in the IBK format of the Sound Blaster Instrument Maker.
+///
+/// Layout: `IBK\x1A`, 128 instrument records of 16 bytes and 128 names of 9 bytes.
+/// The records are like the SBI format: the registers 0x20, 0x40, 0x60, 0x80 and 0xE0 for the modulator and
+/// carrier in turn, the feedback and synthesis type, the percussion voice, a transpose and 3 unused bytes.
+/// The bank is read straight from the bytes, so it takes no extra memory.
+#[derive(Debug, Copy, Clone)]
+pub struct InstrumentBank<'a> {
+    records: RecordBank<'a>,
+}
+
+impl<'a> InstrumentBank<'a> {
+    /// The amount of instruments in a bank
+    pub const LEN: usize = 128;
+    /// The size of an IBK file
+    pub const FILE_SIZE: usize = LAYOUT.file_size();
+
+    /// Returns `None` if the bytes are not an IBK file
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            records: LAYOUT.parse(bytes)?,
+        })
+    }
+
+    /// The instrument at the index (0-127)
+    pub fn instrument(&self, index: usize) -> Option<MelodyInstrument> {
+        Some(MelodyInstrument::from_sbi_record(
+            self.records.record(index)?,
+        ))
+    }
+
+    /// The semitones that the notes of the instrument are moved by
+    pub fn transpose(&self, index: usize) -> Option<i8> {
+        Some(self.records.record(index)?[12] as i8)
+    }
+
+    /// The name of the instrument, or `None` if the index is out of range.
+    ///
+    /// Names that aren't valid UTF-8, like the CP437 names of DOS tools, are an empty string.
+    pub fn name(&self, index: usize) -> Option<&'a str> {
+        self.records.name(index)
+    }
+
+    /// Finds the instrument by its name, ignoring upper and lower case
+    pub fn find(&self, name: &str) -> Option<(usize, MelodyInstrument)> {
+        self.iter()
+            .find(|(_, candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|(index, _, instrument)| (index, instrument))
+    }
+
+    /// The index, name and instrument of every entry in the order of the bank
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &'a str, MelodyInstrument)> {
+        self.records
+            .iter()
+            .map(|(index, name, record)| (index, name, MelodyInstrument::from_sbi_record(record)))
+    }
+}
+
+impl MelodyInstrument {
+    /// Reads the 16 byte instrument record of SBI and IBK files
+    pub(crate) fn from_sbi_record(r: &[u8]) -> Self {
+        Self::from_bytes([
+            r[0], r[2], r[4], r[6], r[8], r[10], r[1], r[3], r[5], r[7], r[9],
+        ])
+    }
+
+    /// The 16 byte instrument record of SBI and IBK files, with the percussion voice, transpose and padding at 0
+    pub(crate) fn to_sbi_record(self) -> [u8; RECORD_SIZE] {
+        let b = self.to_bytes();
+        [
+            b[0], b[6], b[1], b[7], b[2], b[8], b[3], b[9], b[4], b[10], b[5], 0, 0, 0, 0, 0,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bank_bytes() -> [u8; InstrumentBank::FILE_SIZE] {
+        let mut bytes = [0; InstrumentBank::FILE_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        for index in 0..InstrumentBank::LEN {
+            let instrument = MelodyInstrument::from_bytes([index as u8; 11]);
+            let start = 4 + index * RECORD_SIZE;
+            bytes[start..start + RECORD_SIZE].copy_from_slice(&instrument.to_sbi_record());
+        }
+        bytes
+    }
+
+    fn set_name(bytes: &mut [u8], index: usize, name: &[u8]) {
+        let start = 4 + InstrumentBank::LEN * RECORD_SIZE + index * NAME_SIZE;
+        bytes[start..start + name.len()].copy_from_slice(name);
+    }
+
+    #[test]
+    fn instruments_round_trip() {
+        let mut bytes = bank_bytes();
+        set_name(&mut bytes, 5, b"PIANO");
+        bytes[4 + 5 * RECORD_SIZE + 12] = -2i8 as u8;
+
+        let bank = InstrumentBank::parse(&bytes).unwrap();
+        for index in 0..InstrumentBank::LEN {
+            let instrument = bank.instrument(index).unwrap();
+            assert_eq!(instrument.to_bytes(), [index as u8; 11]);
+        }
+        assert_eq!(bank.name(5), Some("PIANO"));
+        assert_eq!(bank.transpose(5), Some(-2));
+        assert_eq!(bank.find("piano").unwrap().0, 5);
+        assert!(bank.instrument(InstrumentBank::LEN).is_none());
+    }
+
+    #[test]
+    fn names_that_arent_utf8_keep_their_entry() {
+        let mut bytes = bank_bytes();
+        // "Übung" in CP437
+        set_name(&mut bytes, 7, &[0x9A, b'b', b'u', b'n', b'g']);
+        set_name(&mut bytes, 8, b"FLUTE");
+
+        let bank = InstrumentBank::parse(&bytes).unwrap();
+        assert_eq!(bank.name(7), Some(""));
+        assert_eq!(bank.iter().count(), InstrumentBank::LEN);
+        assert!(bank
+            .iter()
+            .all(|(index, _, instrument)| instrument.to_bytes() == [index as u8; 11]));
+        assert_eq!(bank.find("flute").unwrap().0, 8);
+    }
+
+    #[test]
+    fn parse_rejects_other_files() {
+        let mut bytes = bank_bytes();
+        assert!(InstrumentBank::parse(&bytes[..InstrumentBank::FILE_SIZE - 1]).is_none());
+        bytes[0] = b'X';
+        assert!(InstrumentBank::parse(&bytes).is_none());
+    }
+}
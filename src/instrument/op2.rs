@@ -0,0 +1,267 @@
+use super::bank::{BankLayout, RecordBank};
+use super::MelodyInstrument;
+
+/// The magic number at the start of an OP2 file
+const MAGIC: &[u8; 8] = b"#OPL_II#";
+/// The size of an instrument record
+const RECORD_SIZE: usize = 36;
+/// The size of a voice in an instrument record
+const VOICE_SIZE: usize = 16;
+/// The size of a name, including its terminating zero
+const NAME_SIZE: usize = 32;
+
+const LAYOUT: BankLayout = BankLayout {
+    magic: MAGIC,
+    len: Op2Bank::LEN,
+    record_size: RECORD_SIZE,
+    name_size: NAME_SIZE,
+};
+
+/// The instrument always plays at its fixed note
+const FLAG_FIXED_PITCH: u16 = 0x0001;
+/// The instrument plays both of its voices for every note
+const FLAG_DOUBLE_VOICE: u16 = 0x0004;
+
+/// One of the two voices of an [Op2Instrument]
+#[derive(Debug, Copy, Clone)]
+pub struct Op2Voice {
+    pub instrument: MelodyInstrument,
+    /// The semitones that the notes of the voice are moved by
+    pub note_offset: i16,
+}
+
+/// An instrument of an [Op2Bank]
+#[derive(Debug, Copy, Clone)]
+pub struct Op2Instrument {
+    pub voice: Op2Voice,
+    /// The voice that plays together with the first one, for the double-voice instruments
+    pub second_voice: Option<Op2Voice>,
+    /// The MIDI note that the instrument plays at whatever note it gets, used by most of the percussion
+    pub fixed_note: Option<u8>,
+    /// The detune of the second voice, where 128 is in tune and every step is 1/64 semitone
+    pub fine_tune: u8,
+}
+
+impl Op2Instrument {
+    /// The detune of the second voice in cents
+    pub fn second_voice_detune_cents(&self) -> i32 {
+        (self.fine_tune as i32 - 128) * 100 / 64
+    }
+}
+
+/// A bank of 175 instruments in the OP2 format, like the GENMIDI lump of Doom.
+///
+/// Layout: `#OPL_II#`, 175 instrument records of 36 bytes and 175 names of 32 bytes.
+/// The first 128 instruments are the General MIDI programs, the other 47 are the percussion for the MIDI notes 35-81.
+///
+/// A record has the flags as a little endian u16, the fine tune, the fixed note and two voices of 16 bytes.
+/// A voice has the registers 0x20, 0x60, 0x80, 0xE0, the key scale level and the total level of the modulator,
+/// the register 0xC0, the same six bytes for the carrier, an unused byte and the note offset as a little endian i16.
+/// The bank is read straight from the bytes, so it takes no extra memory.
+#[derive(Debug, Copy, Clone)]
+pub struct Op2Bank<'a> {
+    records: RecordBank<'a>,
+}
+
+impl<'a> Op2Bank<'a> {
+    /// The amount of instruments in a bank
+    pub const LEN: usize = 175;
+    /// The amount of General MIDI programs, the percussion comes after them
+    pub const PROGRAM_COUNT: usize = 128;
+    /// The MIDI note of the first percussion instrument
+    pub const FIRST_PERCUSSION_NOTE: u8 = 35;
+    /// The size of an OP2 file
+    pub const FILE_SIZE: usize = LAYOUT.file_size();
+
+    /// Returns `None` if the bytes are not an OP2 file
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            records: LAYOUT.parse(bytes)?,
+        })
+    }
+
+    /// The instrument at the index (0-174)
+    pub fn instrument(&self, index: usize) -> Option<Op2Instrument> {
+        Some(Self::record_instrument(self.records.record(index)?))
+    }
+
+    fn record_instrument(r: &[u8]) -> Op2Instrument {
+        let flags = u16::from_le_bytes([r[0], r[1]]);
+
+        Op2Instrument {
+            voice: Self::voice(&r[4..4 + VOICE_SIZE]),
+            second_voice: if flags & FLAG_DOUBLE_VOICE != 0 {
+                Some(Self::voice(&r[4 + VOICE_SIZE..4 + 2 * VOICE_SIZE]))
+            } else {
+                None
+            },
+            fixed_note: if flags & FLAG_FIXED_PITCH != 0 {
+                Some(r[3])
+            } else {
+                None
+            },
+            fine_tune: r[2],
+        }
+    }
+
+    /// The instrument of the General MIDI program (0-127)
+    pub fn program(&self, program: u8) -> Option<Op2Instrument> {
+        if program as usize >= Self::PROGRAM_COUNT {
+            return None;
+        }
+
+        self.instrument(program as usize)
+    }
+
+    /// The instrument for the MIDI note on the percussion channel (35-81)
+    pub fn percussion(&self, note: u8) -> Option<Op2Instrument> {
+        let index = Self::PROGRAM_COUNT + note.checked_sub(Self::FIRST_PERCUSSION_NOTE)? as usize;
+        self.instrument(index)
+    }
+
+    /// The name of the instrument, or `None` if the index is out of range.
+    ///
+    /// Names that aren't valid UTF-8 are an empty string.
+    pub fn name(&self, index: usize) -> Option<&'a str> {
+        self.records.name(index)
+    }
+
+    /// The index, name and instrument of every entry in the order of the bank
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &'a str, Op2Instrument)> {
+        self.records
+            .iter()
+            .map(|(index, name, record)| (index, name, Self::record_instrument(record)))
+    }
+
+    fn voice(v: &[u8]) -> Op2Voice {
+        // The key scale level is stored in the top two bits, like in register 0x40
+        Op2Voice {
+            instrument: MelodyInstrument::from_bytes([
+                v[0],
+                (v[4] & 0xC0) | (v[5] & 0x3F),
+                v[1],
+                v[2],
+                v[3],
+                v[6],
+                v[7],
+                (v[11] & 0xC0) | (v[12] & 0x3F),
+                v[8],
+                v[9],
+                v[10],
+            ]),
+            note_offset: i16::from_le_bytes([v[14], v[15]]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIANO: [u8; 11] = [
+        0x01, 0x4F, 0xF1, 0x53, 0x00, 0x06, 0x11, 0x00, 0xD2, 0x74, 0x01,
+    ];
+
+    /// The 16 bytes of an OP2 voice that plays the instrument
+    fn voice_bytes(instrument: [u8; 11], note_offset: i16) -> [u8; VOICE_SIZE] {
+        let offset = note_offset.to_le_bytes();
+        [
+            instrument[0],
+            instrument[2],
+            instrument[3],
+            instrument[4],
+            instrument[1] & 0xC0,
+            instrument[1] & 0x3F,
+            instrument[5],
+            instrument[6],
+            instrument[8],
+            instrument[9],
+            instrument[10],
+            instrument[7] & 0xC0,
+            instrument[7] & 0x3F,
+            0,
+            offset[0],
+            offset[1],
+        ]
+    }
+
+    fn set_record(bytes: &mut [u8], index: usize, flags: u16, fine_tune: u8, fixed_note: u8) {
+        let start = MAGIC.len() + index * RECORD_SIZE;
+        let record = &mut bytes[start..start + RECORD_SIZE];
+        record[0..2].copy_from_slice(&flags.to_le_bytes());
+        record[2] = fine_tune;
+        record[3] = fixed_note;
+        record[4..4 + VOICE_SIZE].copy_from_slice(&voice_bytes(PIANO, -12));
+        record[4 + VOICE_SIZE..].copy_from_slice(&voice_bytes([0x21; 11], 7));
+    }
+
+    fn set_name(bytes: &mut [u8], index: usize, name: &[u8]) {
+        let start = MAGIC.len() + Op2Bank::LEN * RECORD_SIZE + index * NAME_SIZE;
+        bytes[start..start + name.len()].copy_from_slice(name);
+    }
+
+    fn bank_bytes() -> [u8; Op2Bank::FILE_SIZE] {
+        let mut bytes = [0; Op2Bank::FILE_SIZE];
+        bytes[..MAGIC.len()].copy_from_slice(MAGIC);
+        set_record(&mut bytes, 0, FLAG_DOUBLE_VOICE, 132, 0);
+        set_name(&mut bytes, 0, b"Acoustic Grand Piano");
+        set_record(
+            &mut bytes,
+            Op2Bank::PROGRAM_COUNT,
+            FLAG_FIXED_PITCH,
+            128,
+            60,
+        );
+        set_name(&mut bytes, Op2Bank::PROGRAM_COUNT, b"Acoustic Bass Drum");
+        bytes
+    }
+
+    #[test]
+    fn instruments_round_trip() {
+        let bytes = bank_bytes();
+        let bank = Op2Bank::parse(&bytes).unwrap();
+
+        let piano = bank.program(0).unwrap();
+        assert_eq!(piano.voice.instrument.to_bytes(), PIANO);
+        assert_eq!(piano.voice.note_offset, -12);
+        let second = piano.second_voice.unwrap();
+        assert_eq!(second.instrument.to_bytes(), [0x21; 11]);
+        assert_eq!(second.note_offset, 7);
+        assert_eq!(piano.fixed_note, None);
+        assert_eq!(piano.second_voice_detune_cents(), 6);
+        assert_eq!(bank.name(0), Some("Acoustic Grand Piano"));
+
+        let bass_drum = bank.percussion(35).unwrap();
+        assert_eq!(bass_drum.fixed_note, Some(60));
+        assert!(bass_drum.second_voice.is_none());
+        assert_eq!(
+            bank.name(Op2Bank::PROGRAM_COUNT),
+            Some("Acoustic Bass Drum")
+        );
+
+        assert!(bank.program(128).is_none());
+        assert!(bank.percussion(34).is_none());
+        assert!(bank.instrument(Op2Bank::LEN).is_none());
+    }
+
+    #[test]
+    fn names_that_arent_utf8_keep_their_entry() {
+        let mut bytes = bank_bytes();
+        set_name(&mut bytes, 0, &[0x82, b't', b'u', b'd', b'e']);
+
+        let bank = Op2Bank::parse(&bytes).unwrap();
+        assert_eq!(bank.name(0), Some(""));
+        assert_eq!(bank.iter().count(), Op2Bank::LEN);
+        let (index, name, piano) = bank.iter().next().unwrap();
+        assert_eq!((index, name), (0, ""));
+        assert_eq!(piano.voice.instrument.to_bytes(), PIANO);
+    }
+
+    #[test]
+    fn parse_rejects_other_files() {
+        let mut bytes = bank_bytes();
+        assert!(Op2Bank::parse(&bytes[..Op2Bank::FILE_SIZE - 1]).is_none());
+        bytes[0] = b'X';
+        assert!(Op2Bank::parse(&bytes).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::hl::{Initialized, Opl2, Opl2Error};
+use crate::ll::HardwareInterface;
+use crate::tuning::{ChipClock, Pitch};
+
+/// The waveform of an [Lfo]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+    /// Rises over the period and then falls back at once
+    Saw,
+}
+
+impl LfoShape {
+    /// The value of the shape at the phase, from -1000 to 1000
+    fn value(&self, phase: u32) -> i32 {
+        // The position in the period from 0 to 65535
+        let t = (phase >> 16) as i32;
+
+        match self {
+            LfoShape::Sine => {
+                // Every half period is a parabola, which is within 6% of a sine
+                let half = t & 0x7FFF;
+                let value = ((4000 * half as i64 * (0x8000 - half) as i64) >> 30) as i32;
+                if t < 0x8000 {
+                    value
+                } else {
+                    -value
+                }
+            }
+            LfoShape::Triangle => match t {
+                0..=0x3FFF => t * 1000 / 0x4000,
+                0x4000..=0xBFFF => 1000 - (t - 0x4000) * 1000 / 0x4000,
+                _ => (t - 0xC000) * 1000 / 0x4000 - 1000,
+            },
+            LfoShape::Square if t < 0x8000 => 1000,
+            LfoShape::Square => -1000,
+            LfoShape::Saw => t * 2000 / 0x10000 - 1000,
+        }
+    }
+}
+
+/// What an [Lfo] modulates
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LfoTarget {
+    /// Vibrato: the pitch goes up and down by at most this many cents
+    Pitch { cents: u16 },
+    /// Tremolo: the volume goes down by at most this many steps of 0.75 dB
+    Level { steps: u8 },
+}
+
+/// A software LFO for vibrato and tremolo that go deeper than the chip can.
+///
+/// The vibrato of the chip is at most 14 cents and its tremolo at most 4.8 dB, both at a fixed rate.
+/// This one can go as deep and as fast as wanted, at the cost of register writes on every change.
+/// Call [Self::tick] regularly with the time that has passed. The registers are only written when the
+/// value changes enough to make a difference on the chip.
+///
+/// The level is changed through [Opl2::set_channel_volume], so it replaces the volume of the channel.
+pub struct Lfo {
+    channel: usize,
+    shape: LfoShape,
+    target: LfoTarget,
+    rate_millihertz: u32,
+    /// The position in the period, one period is 2^32
+    phase: u32,
+    /// The pitch the vibrato goes around, set by [Self::set_center]
+    center: Option<Pitch>,
+    /// The frequency registers that were written last
+    written_frequency: Option<(u16, u8)>,
+    /// The volume that was written last
+    written_volume: Option<u8>,
+}
+
+impl Lfo {
+    pub fn new(channel: usize, shape: LfoShape, rate_millihertz: u32, target: LfoTarget) -> Self {
+        Self {
+            channel,
+            shape,
+            target,
+            rate_millihertz,
+            phase: 0,
+            center: None,
+            written_frequency: None,
+            written_volume: None,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate_millihertz: u32) {
+        self.rate_millihertz = rate_millihertz;
+    }
+
+    pub fn set_target(&mut self, target: LfoTarget) {
+        self.target = target;
+        self.forget_written();
+    }
+
+    /// Sets the pitch the vibrato goes around. Call it every time the channel gets a new note.
+    ///
+    /// Without a center the pitch isn't touched.
+    pub fn set_center(&mut self, pitch: Pitch) {
+        self.center = Some(pitch);
+        self.forget_written();
+    }
+
+    /// Starts the waveform from the beginning again, like at the start of a note
+    pub fn restart(&mut self) {
+        self.phase = 0;
+        self.forget_written();
+    }
+
+    /// Makes the next tick write the registers, even if the value didn't change
+    fn forget_written(&mut self) {
+        self.written_frequency = None;
+        self.written_volume = None;
+    }
+
+    /// Moves the LFO forward by the time that has passed and updates the channel
+    pub fn tick<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        elapsed_us: u32,
+        clock: &ChipClock,
+    ) -> Result<(), Opl2Error> {
+        let increment = ((self.rate_millihertz as u128 * elapsed_us as u128) << 32) / 1_000_000_000;
+        self.phase = self.phase.wrapping_add(increment as u32);
+        let value = self.shape.value(self.phase);
+
+        match self.target {
+            LfoTarget::Pitch { cents } => {
+                let center = match self.center {
+                    Some(center) => center,
+                    None => return Ok(()),
+                };
+                let pitch = center.transposed(cents as i64 * value as i64 / 1000);
+                let registers = clock.saturating_frequency_number(pitch.millihertz());
+
+                if self.written_frequency != Some(registers) {
+                    self.written_frequency = Some(registers);
+                    opl.set_channel_frequency(self.channel, registers.0, registers.1)?;
+                }
+            }
+            LfoTarget::Level { steps } => {
+                // The tremolo only makes the channel softer, so the top of the wave is the level of the instrument
+                let attenuation = (steps.min(63) as i32 * (1000 - value) / 2000) as u8;
+                let volume = 63 - attenuation;
+
+                if self.written_volume != Some(volume) {
+                    self.written_volume = Some(volume);
+                    opl.set_channel_volume(self.channel, volume)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
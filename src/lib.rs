@@ -1,5 +1,36 @@
 #![no_std]
 
+#[macro_use]
+mod logging;
+
+pub mod array;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod clock;
+#[cfg(feature = "embedded-storage")]
+pub mod content;
+pub mod cv;
+pub mod diagnostics;
+pub mod dual;
+pub mod editor;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+pub mod entropy;
+#[cfg(feature = "examples-support")]
+pub mod examples_support;
+pub mod glide;
 pub mod hl;
 pub mod instrument;
+pub mod lfo;
 pub mod ll;
+pub mod observer;
+mod planner;
+pub mod player;
+#[cfg(feature = "rhythm")]
+pub mod rhythm_only;
+pub mod ripper;
+pub mod store;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod tuning;
+pub mod voices;
@@ -9,12 +9,50 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 pub use device_driver::Bit;
 
-#[derive(Debug)]
+#[cfg(feature = "embedded-hal-1")]
+mod eh1;
+#[cfg(feature = "embedded-hal-1")]
+pub use eh1::{Eh1, Eh1ShiftInterface};
+mod dynamic;
+pub use dynamic::DynInterface;
+mod mcp23017;
+pub use mcp23017::Mcp23017Interface;
+mod parallel;
+pub use parallel::ParallelInterface;
+mod recording;
+pub use recording::{RecordingInterface, WriteRecorder};
+#[cfg(feature = "async")]
+mod asynch;
+#[cfg(feature = "async")]
+pub use asynch::{AsyncHardwareInterface, AsyncShiftInterface};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum InterfaceError {
     AddressPinError,
     LatchPinError,
     ResetPinError,
     CommunicationError,
+    DataPinError,
+    WritePinError,
+    ChipSelectPinError,
+    /// More writes were done at once than the interface can hold
+    TooManyWrites,
+}
+
+impl InterfaceError {
+    /// The name of the error, for the trace log
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            InterfaceError::AddressPinError => "address pin error",
+            InterfaceError::LatchPinError => "latch pin error",
+            InterfaceError::ResetPinError => "reset pin error",
+            InterfaceError::CommunicationError => "communication error",
+            InterfaceError::DataPinError => "data pin error",
+            InterfaceError::WritePinError => "write pin error",
+            InterfaceError::ChipSelectPinError => "chip select pin error",
+            InterfaceError::TooManyWrites => "too many writes",
+        }
+    }
 }
 
 /// Our hardware interface with the chip using the shift register that is present on the opl2 audio board by Maarten Janssen
@@ -72,6 +110,49 @@ impl<SPI: Write<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayUs<u8> +
             self.reset_pin,
         )
     }
+
+    /// Sends one register write to the chip
+    fn send(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        // Send the address
+        self.address_pin
+            .set_low()
+            .map_err(|_| InterfaceError::AddressPinError)?;
+
+        self.communication_interface
+            .write(&[address])
+            .map_err(|_| InterfaceError::CommunicationError)?;
+
+        // Apply the shift latch
+        self.latch_pin
+            .set_low()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.delay.delay_us(1);
+        self.latch_pin
+            .set_high()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.delay.delay_us(4);
+
+        // Send the data
+        self.address_pin
+            .set_high()
+            .map_err(|_| InterfaceError::AddressPinError)?;
+
+        self.communication_interface
+            .write(&[value])
+            .map_err(|_| InterfaceError::CommunicationError)?;
+
+        // Apply the shift latch
+        self.latch_pin
+            .set_low()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.delay.delay_us(1);
+        self.latch_pin
+            .set_high()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.delay.delay_us(23);
+
+        Ok(())
+    }
 }
 
 impl<SPI: Write<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayUs<u8> + DelayMs<u8>>
@@ -98,9 +179,8 @@ impl<SPI: Write<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayUs<u8> +
             .set_high()
             .map_err(|_| InterfaceError::ResetPinError)?;
 
-        // Reset the internal registers
+        // The chip has cleared its registers, so the image must follow
         self.registers = [0x00; 0xFF];
-        self.write_register(0x00, &[0x00; 0xFF])?;
 
         Ok(())
     }
@@ -128,53 +208,160 @@ impl<SPI: Write<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayUs<u8> +
         address: Self::Address,
         value: &[u8],
     ) -> Result<(), Self::InterfaceError> {
-        // Save in internal data store
-        self.registers[(address as usize)..(address as usize + value.len())].copy_from_slice(value);
-
         for (i, val) in value.iter().enumerate() {
-            // Send the address
-            self.address_pin
-                .set_low()
-                .map_err(|_| Self::InterfaceError::AddressPinError)?;
-
-            self.communication_interface
-                .write(&[address + i as u8])
-                .map_err(|_| Self::InterfaceError::CommunicationError)?;
-
-            // Apply the shift latch
-            self.latch_pin
-                .set_low()
-                .map_err(|_| Self::InterfaceError::LatchPinError)?;
-            self.delay.delay_us(1);
-            self.latch_pin
-                .set_high()
-                .map_err(|_| Self::InterfaceError::LatchPinError)?;
-            self.delay.delay_us(4);
-
-            // Send the data
-            self.address_pin
-                .set_high()
-                .map_err(|_| Self::InterfaceError::AddressPinError)?;
-
-            self.communication_interface
-                .write(&[*val])
-                .map_err(|_| Self::InterfaceError::CommunicationError)?;
-
-            // Apply the shift latch
-            self.latch_pin
-                .set_low()
-                .map_err(|_| Self::InterfaceError::LatchPinError)?;
-            self.delay.delay_us(1);
-            self.latch_pin
-                .set_high()
-                .map_err(|_| Self::InterfaceError::LatchPinError)?;
-            self.delay.delay_us(23);
+            self.send(address.wrapping_add(i as u8), *val)?;
+
+            // Save in internal data store once the chip has it, so the image never runs ahead of the chip
+            if let Some(register) = self.registers.get_mut(address as usize + i) {
+                *register = *val;
+            }
         }
 
         Ok(())
     }
 }
 
+/// An interface that can also read the status register of the chip.
+///
+/// Reading needs a bidirectional data bus and the /RD pin, which the OPL2 Audio Board doesn't have,
+/// so this is an optional addition to the [HardwareInterface].
+pub trait StatusInterface: HardwareInterface {
+    /// Reads the status register: bit 7 is the IRQ flag, bit 6 the timer 1 flag and bit 5 the timer 2 flag
+    fn read_status(&mut self) -> Result<u8, InterfaceError>;
+}
+
+/// A hardware interface that only exists in memory.
+///
+/// Nothing is sent anywhere, the writes only end up in the register image.
+/// This is useful for turning register values into raw bytes.
+pub struct MemoryInterface {
+    registers: [u8; 256],
+}
+
+impl MemoryInterface {
+    pub const fn new() -> Self {
+        Self {
+            registers: [0; 256],
+        }
+    }
+
+    /// The current register image
+    pub fn registers(&self) -> &[u8; 256] {
+        &self.registers
+    }
+}
+
+impl Default for MemoryInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HardwareInterface for MemoryInterface {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        self.registers = [0; 256];
+        Ok(())
+    }
+}
+
+impl RegisterInterface for MemoryInterface {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        value
+            .copy_from_slice(&self.registers[(address as usize)..(address as usize + value.len())]);
+        Ok(())
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.registers[(address as usize)..(address as usize + value.len())].copy_from_slice(value);
+        Ok(())
+    }
+}
+
+/// One of the nine channels of the chip
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChannelId(u8);
+
+impl ChannelId {
+    pub const COUNT: usize = 9;
+    /// The channel that plays the bass drum in rhythm mode
+    pub const BASS_DRUM: Self = Self(6);
+    /// The channel that plays the snare drum and hi-hat in rhythm mode
+    pub const SNARE_DRUM_HI_HAT: Self = Self(7);
+    /// The channel that plays the tom-tom and cymbal in rhythm mode
+    pub const TOM_TOM_CYMBAL: Self = Self(8);
+
+    /// Returns `None` if the index is 9 or higher
+    pub const fn new(index: usize) -> Option<Self> {
+        if index < Self::COUNT {
+            Some(Self(index as u8))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// One of the two operators of a channel
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum Operator {
+    /// The first operator. With FM synthesis it modulates the carrier, with additive synthesis it's heard too.
+    Modulator = 0,
+    /// The second operator. It is always heard.
+    Carrier = 1,
+}
+
+/// Gives the index of the operator in the operator registers (0x20-0x35, 0x40-0x55, 0x60-0x75, 0x80-0x95 and 0xE0-0xF5)
+pub const fn operator_register_index(channel: ChannelId, operator: Operator) -> usize {
+    let modulator = (channel.0 as usize / 3) * 8 + channel.0 as usize % 3;
+
+    match operator {
+        Operator::Modulator => modulator,
+        Operator::Carrier => modulator + 3,
+    }
+}
+
+// Check the operator mapping against the table of the datasheet at compile time
+const _: () = {
+    const DATASHEET_OPERATOR_MAP: [(usize, usize); ChannelId::COUNT] = [
+        (0x00, 0x03),
+        (0x01, 0x04),
+        (0x02, 0x05),
+        (0x08, 0x0B),
+        (0x09, 0x0C),
+        (0x0A, 0x0D),
+        (0x10, 0x13),
+        (0x11, 0x14),
+        (0x12, 0x15),
+    ];
+
+    let mut channel = 0;
+    while channel < ChannelId::COUNT {
+        let id = ChannelId(channel as u8);
+        assert!(
+            operator_register_index(id, Operator::Modulator) == DATASHEET_OPERATOR_MAP[channel].0
+        );
+        assert!(
+            operator_register_index(id, Operator::Carrier) == DATASHEET_OPERATOR_MAP[channel].1
+        );
+        channel += 1;
+    }
+};
+
 // Create our low level device. This holds all the hardware communication definitions
 create_low_level_device!(
     /// Low level access to the Opl2 chip
@@ -185,6 +372,17 @@ create_low_level_device!(
         hardware_interface_capabilities: {
             /// Asserts the reset pin
             fn reset(&mut self) -> Result<(), InterfaceError>;
+            /// Writes a set of single registers in the given order.
+            ///
+            /// The default writes them one by one. An interface can take the whole set at once instead,
+            /// as long as the outcome is the same.
+            fn write_batch(&mut self, writes: &[(u8, u8)]) -> Result<(), InterfaceError> {
+                for (address, value) in writes {
+                    self.write_register(*address, &[*value])?;
+                }
+
+                Ok(())
+            }
         }
     }
 );
@@ -297,7 +495,8 @@ implement_registers!(
             synthesis_type: u8 as SynthesisType = RW 0..=0,
         },
         operator_settings4(RW, [0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xEB, 0xEC, 0xED, 0xEE, 0xEF, 0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5], 1) = {
-            waveform: u8 as WaveformType = RW 0..=1,
+            /// The chip only uses the lowest 2 bits. The third bit is only kept so OPL3 instruments round-trip, see [WaveformType].
+            waveform: u8 as WaveformType = RW 0..=2,
         },
     }
 );
@@ -386,7 +585,12 @@ pub enum SynthesisType {
     AdditiveSynthesis = 0b1,
 }
 
-/// 2 bits
+/// 3 bits, of which the chip only hears the lowest 2.
+///
+/// The last 4 waveforms are OPL3 waveforms that only play when the OPL3 NEW bit is set. That bit is in the second
+/// register bank, which this driver can't address, so an OPL3 runs in OPL2 mode and masks them to the first 4.
+/// They are here so instruments from OPL3 formats like WOPL can be loaded and saved again without losing the bit,
+/// but they never sound different from the waveform in their lowest 2 bits.
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 pub enum WaveformType {
@@ -422,4 +626,12 @@ pub enum WaveformType {
     ///     
     /// ```
     PulseSine = 0b11,
+    /// OPL3 only, plays as [Self::Sine]: a sine at double speed in the first half of every period and silence in the second half
+    AlternatingSine = 0b100,
+    /// OPL3 only, plays as [Self::HalfSine]: [Self::AbsSine] at double speed in the first half of every period and silence in the second half
+    CamelSine = 0b101,
+    /// OPL3 only, plays as [Self::AbsSine]: a full-scale square wave
+    Square = 0b110,
+    /// OPL3 only, plays as [Self::PulseSine]: a square wave of which every half period decays logarithmically, sounding like a sawtooth
+    LogarithmicSawtooth = 0b111,
 }
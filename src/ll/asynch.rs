@@ -0,0 +1,141 @@
+use super::InterfaceError;
+use embedded_hal_1::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiBus;
+
+/// The async counterpart of the [super::HardwareInterface].
+///
+/// There's no register read because [crate::asynch::AsyncOpl2] keeps the register image itself.
+#[allow(async_fn_in_trait)]
+pub trait AsyncHardwareInterface {
+    /// Asserts the reset pin
+    async fn reset(&mut self) -> Result<(), InterfaceError>;
+    /// Writes a set of single registers in the given order
+    async fn write_batch(&mut self, writes: &[(u8, u8)]) -> Result<(), InterfaceError>;
+}
+
+/// The [super::ShiftInterface] with async peripherals, so the delays after every write can be awaited
+pub struct AsyncShiftInterface<
+    SPI: SpiBus<u8>,
+    A: OutputPin,
+    L: OutputPin,
+    R: OutputPin,
+    D: DelayNs,
+> {
+    /// The spi interface we use to drive the shift register
+    communication_interface: SPI,
+    /// The pin connected to the A0 input
+    address_pin: A,
+    /// The pin connected to the latch input of the shift register
+    latch_pin: L,
+    /// The pin connected to the reset input
+    reset_pin: R,
+    /// Some kind of delay provider
+    delay: D,
+}
+
+impl<SPI: SpiBus<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayNs>
+    AsyncShiftInterface<SPI, A, L, R, D>
+{
+    /// Creates a new hardware interface
+    pub fn new(
+        communication_interface: SPI,
+        address_pin: A,
+        latch_pin: L,
+        reset_pin: R,
+        delay: D,
+    ) -> Self {
+        Self {
+            communication_interface,
+            address_pin,
+            latch_pin,
+            reset_pin,
+            delay,
+        }
+    }
+
+    /// Destructs the hardware interface into its pieces.
+    pub fn free(self) -> (SPI, A, L, R, D) {
+        (
+            self.communication_interface,
+            self.address_pin,
+            self.latch_pin,
+            self.reset_pin,
+            self.delay,
+        )
+    }
+
+    /// Shifts out the byte and latches it into the chip
+    async fn shift(&mut self, byte: u8) -> Result<(), InterfaceError> {
+        self.communication_interface
+            .write(&[byte])
+            .await
+            .map_err(|_| InterfaceError::CommunicationError)?;
+        self.communication_interface
+            .flush()
+            .await
+            .map_err(|_| InterfaceError::CommunicationError)?;
+
+        // Apply the shift latch
+        self.latch_pin
+            .set_low()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.delay.delay_us(1).await;
+        self.latch_pin
+            .set_high()
+            .map_err(|_| InterfaceError::LatchPinError)
+    }
+
+    /// Sends one register write to the chip
+    async fn send(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        // Send the address
+        self.address_pin
+            .set_low()
+            .map_err(|_| InterfaceError::AddressPinError)?;
+        self.shift(address).await?;
+        self.delay.delay_us(4).await;
+
+        // Send the data
+        self.address_pin
+            .set_high()
+            .map_err(|_| InterfaceError::AddressPinError)?;
+        self.shift(value).await?;
+        self.delay.delay_us(23).await;
+
+        Ok(())
+    }
+}
+
+impl<SPI: SpiBus<u8>, A: OutputPin, L: OutputPin, R: OutputPin, D: DelayNs> AsyncHardwareInterface
+    for AsyncShiftInterface<SPI, A, L, R, D>
+{
+    async fn reset(&mut self) -> Result<(), InterfaceError> {
+        // Set the pins to the default level
+        self.latch_pin
+            .set_high()
+            .map_err(|_| InterfaceError::LatchPinError)?;
+        self.reset_pin
+            .set_high()
+            .map_err(|_| InterfaceError::ResetPinError)?;
+        self.address_pin
+            .set_low()
+            .map_err(|_| InterfaceError::AddressPinError)?;
+
+        // Make a reset cycle
+        self.reset_pin
+            .set_low()
+            .map_err(|_| InterfaceError::ResetPinError)?;
+        self.delay.delay_ms(1).await;
+        self.reset_pin
+            .set_high()
+            .map_err(|_| InterfaceError::ResetPinError)
+    }
+
+    async fn write_batch(&mut self, writes: &[(u8, u8)]) -> Result<(), InterfaceError> {
+        for (address, value) in writes {
+            self.send(*address, *value).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use super::{HardwareInterface, InterfaceError};
+use device_driver::ll::register::RegisterInterface;
+
+/// Hardware interface that forwards to any other interface through a trait object.
+///
+/// The driver is generic over its interface, so every interface type gets its own copy of the whole driver.
+/// Firmware that uses more than one interface type, for example the hardware and a tracer around it,
+/// can put all of them behind this wrapper so the driver is only compiled once.
+/// Every register access then goes through a virtual call.
+pub struct DynInterface<'a> {
+    interface: &'a mut dyn HardwareInterface,
+}
+
+impl<'a> DynInterface<'a> {
+    pub fn new(interface: &'a mut dyn HardwareInterface) -> Self {
+        Self { interface }
+    }
+
+    pub fn free(self) -> &'a mut dyn HardwareInterface {
+        self.interface
+    }
+}
+
+impl HardwareInterface for DynInterface<'_> {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        self.interface.reset()
+    }
+
+    fn write_batch(&mut self, writes: &[(u8, u8)]) -> Result<(), InterfaceError> {
+        self.interface.write_batch(writes)
+    }
+}
+
+impl RegisterInterface for DynInterface<'_> {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.interface.read_register(address, value)
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.interface.write_register(address, value)
+    }
+}
@@ -0,0 +1,78 @@
+use super::ShiftInterface;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Makes an embedded-hal 1.0 peripheral usable where the driver takes an embedded-hal 0.2 one
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Eh1<T>(pub T);
+
+impl<T> Eh1<T> {
+    pub fn free(self) -> T {
+        self.0
+    }
+}
+
+impl<T: embedded_hal_1::spi::SpiBus<u8>> Write<u8> for Eh1<T> {
+    type Error = T::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(words)?;
+        // The latch may only be toggled when everything has been shifted out
+        self.0.flush()
+    }
+}
+
+impl<T: embedded_hal_1::digital::OutputPin> OutputPin for Eh1<T> {
+    type Error = T::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high()
+    }
+}
+
+impl<T: embedded_hal_1::delay::DelayNs> DelayUs<u8> for Eh1<T> {
+    fn delay_us(&mut self, us: u8) {
+        self.0.delay_us(us as u32);
+    }
+}
+
+impl<T: embedded_hal_1::delay::DelayNs> DelayMs<u8> for Eh1<T> {
+    fn delay_ms(&mut self, ms: u8) {
+        self.0.delay_ms(ms as u32);
+    }
+}
+
+/// A [ShiftInterface] on embedded-hal 1.0 peripherals
+pub type Eh1ShiftInterface<SPI, A, L, R, D> =
+    ShiftInterface<Eh1<SPI>, Eh1<A>, Eh1<L>, Eh1<R>, Eh1<D>>;
+
+impl<SPI, A, L, R, D> Eh1ShiftInterface<SPI, A, L, R, D>
+where
+    SPI: embedded_hal_1::spi::SpiBus<u8>,
+    A: embedded_hal_1::digital::OutputPin,
+    L: embedded_hal_1::digital::OutputPin,
+    R: embedded_hal_1::digital::OutputPin,
+    D: embedded_hal_1::delay::DelayNs,
+{
+    /// Creates a new hardware interface out of embedded-hal 1.0 peripherals
+    pub fn new_eh1(
+        communication_interface: SPI,
+        address_pin: A,
+        latch_pin: L,
+        reset_pin: R,
+        delay: D,
+    ) -> Self {
+        Self::new(
+            Eh1(communication_interface),
+            Eh1(address_pin),
+            Eh1(latch_pin),
+            Eh1(reset_pin),
+            Eh1(delay),
+        )
+    }
+}
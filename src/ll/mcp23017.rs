@@ -0,0 +1,122 @@
+use super::{HardwareInterface, InterfaceError};
+use device_driver::ll::register::RegisterInterface;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::Write;
+
+/// The registers of the expander, in the default bank 0 layout
+const IODIRA: u8 = 0x00;
+const GPIOA: u8 = 0x12;
+const GPIOB: u8 = 0x13;
+
+/// The pins of port B
+const A0: u8 = 0x01;
+const WR: u8 = 0x02;
+const CS: u8 = 0x04;
+const IC: u8 = 0x08;
+
+/// Hardware interface for a chip that is wired to an MCP23017 I2C GPIO expander.
+///
+/// Port A drives D0 through D7 and port B drives the control pins:
+/// GPB0 is A0, GPB1 is /WR, GPB2 is /CS and GPB3 is /IC.
+/// Every register write takes 6 I2C transactions, 3 for the address and 3 for the value,
+/// so writing a full instrument takes a few ms on a 400 kHz bus.
+pub struct Mcp23017Interface<I2C: Write, D: DelayUs<u8> + DelayMs<u8>> {
+    i2c: I2C,
+    /// The I2C address of the expander, 0x20 through 0x27 depending on its address pins
+    address: u8,
+    /// Some kind of delay provider
+    delay: D,
+    /// A copy of all the registers in memory, because the registers can't be read
+    registers: [u8; 256],
+}
+
+impl<I2C: Write, D: DelayUs<u8> + DelayMs<u8>> Mcp23017Interface<I2C, D> {
+    /// Creates a new hardware interface for the expander at the I2C address
+    pub fn new(i2c: I2C, address: u8, delay: D) -> Self {
+        Self {
+            i2c,
+            address,
+            delay,
+            registers: [0; 256],
+        }
+    }
+
+    /// Destructs the hardware interface into its pieces.
+    pub fn free(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+    /// Writes consecutive registers of the expander
+    fn write_expander(&mut self, bytes: &[u8]) -> Result<(), InterfaceError> {
+        self.i2c
+            .write(self.address, bytes)
+            .map_err(|_| InterfaceError::CommunicationError)
+    }
+
+    /// Strobes the byte into the chip with A0 at the given level
+    fn strobe(&mut self, a0: u8, byte: u8) -> Result<(), InterfaceError> {
+        let idle = a0 | WR | CS | IC;
+
+        // Set up the data and the address in one go
+        self.write_expander(&[GPIOA, byte, idle])?;
+        // An I2C transaction takes far longer than the minimal write pulse
+        self.write_expander(&[GPIOB, idle & !(WR | CS)])?;
+        self.write_expander(&[GPIOB, idle])
+    }
+
+    /// Sends one register write to the chip
+    fn send(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        self.strobe(0, address)?;
+        self.delay.delay_us(4);
+        self.strobe(A0, value)?;
+        self.delay.delay_us(23);
+
+        Ok(())
+    }
+}
+
+impl<I2C: Write, D: DelayUs<u8> + DelayMs<u8>> HardwareInterface for Mcp23017Interface<I2C, D> {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        // Set the control pins to their idle level with the reset asserted and make all pins outputs
+        self.write_expander(&[GPIOA, 0x00, WR | CS])?;
+        self.write_expander(&[IODIRA, 0x00, 0x00])?;
+
+        self.delay.delay_ms(1);
+        self.write_expander(&[GPIOB, WR | CS | IC])?;
+
+        // The chip has cleared its registers, so the image must follow
+        self.registers = [0; 256];
+
+        Ok(())
+    }
+}
+
+impl<I2C: Write, D: DelayUs<u8> + DelayMs<u8>> RegisterInterface for Mcp23017Interface<I2C, D> {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        value
+            .copy_from_slice(&self.registers[(address as usize)..(address as usize + value.len())]);
+        Ok(())
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        for (i, val) in value.iter().enumerate() {
+            self.send(address.wrapping_add(i as u8), *val)?;
+
+            // Save in internal data store once the chip has it, so the image never runs ahead of the chip
+            self.registers[address as usize + i] = *val;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use super::{HardwareInterface, InterfaceError};
+use device_driver::ll::register::RegisterInterface;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Hardware interface for a chip that has its data bus, A0, /WR and /CS wired straight to GPIO pins
+pub struct ParallelInterface<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D>
+where
+    D0: OutputPin,
+    D1: OutputPin,
+    D2: OutputPin,
+    D3: OutputPin,
+    D4: OutputPin,
+    D5: OutputPin,
+    D6: OutputPin,
+    D7: OutputPin,
+    A: OutputPin,
+    WR: OutputPin,
+    CS: OutputPin,
+    D: DelayUs<u8>,
+{
+    /// The pins connected to D0 through D7
+    data_pins: (D0, D1, D2, D3, D4, D5, D6, D7),
+    /// The pin connected to the A0 input
+    address_pin: A,
+    /// The pin connected to the /WR input
+    write_pin: WR,
+    /// The pin connected to the /CS input
+    chip_select_pin: CS,
+    /// Some kind of delay provider
+    delay: D,
+    /// A copy of all the registers in memory, because the registers can't be read
+    registers: [u8; 256],
+}
+
+impl<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D>
+    ParallelInterface<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D>
+where
+    D0: OutputPin,
+    D1: OutputPin,
+    D2: OutputPin,
+    D3: OutputPin,
+    D4: OutputPin,
+    D5: OutputPin,
+    D6: OutputPin,
+    D7: OutputPin,
+    A: OutputPin,
+    WR: OutputPin,
+    CS: OutputPin,
+    D: DelayUs<u8>,
+{
+    /// Creates a new hardware interface.
+    ///
+    /// There's no reset pin, so the reset of the driver clears the registers over the bus.
+    /// Don't use [crate::hl::InitConfig::trust_hardware_reset] with this interface.
+    pub fn new(
+        data_pins: (D0, D1, D2, D3, D4, D5, D6, D7),
+        address_pin: A,
+        write_pin: WR,
+        chip_select_pin: CS,
+        delay: D,
+    ) -> Self {
+        Self {
+            data_pins,
+            address_pin,
+            write_pin,
+            chip_select_pin,
+            delay,
+            registers: [0; 256],
+        }
+    }
+
+    /// Destructs the hardware interface into its pieces.
+    #[allow(clippy::type_complexity)]
+    pub fn free(self) -> ((D0, D1, D2, D3, D4, D5, D6, D7), A, WR, CS, D) {
+        (
+            self.data_pins,
+            self.address_pin,
+            self.write_pin,
+            self.chip_select_pin,
+            self.delay,
+        )
+    }
+
+    /// Puts the byte on the data bus
+    fn set_data(&mut self, byte: u8) -> Result<(), InterfaceError> {
+        fn set<P: OutputPin>(pin: &mut P, high: bool) -> Result<(), InterfaceError> {
+            if high { pin.set_high() } else { pin.set_low() }
+                .map_err(|_| InterfaceError::DataPinError)
+        }
+
+        let pins = &mut self.data_pins;
+        set(&mut pins.0, byte & 0x01 != 0)?;
+        set(&mut pins.1, byte & 0x02 != 0)?;
+        set(&mut pins.2, byte & 0x04 != 0)?;
+        set(&mut pins.3, byte & 0x08 != 0)?;
+        set(&mut pins.4, byte & 0x10 != 0)?;
+        set(&mut pins.5, byte & 0x20 != 0)?;
+        set(&mut pins.6, byte & 0x40 != 0)?;
+        set(&mut pins.7, byte & 0x80 != 0)
+    }
+
+    /// Strobes the byte into the chip with A0 at the given level
+    fn strobe(&mut self, a0: bool, byte: u8) -> Result<(), InterfaceError> {
+        if a0 {
+            self.address_pin.set_high()
+        } else {
+            self.address_pin.set_low()
+        }
+        .map_err(|_| InterfaceError::AddressPinError)?;
+        self.set_data(byte)?;
+
+        self.chip_select_pin
+            .set_low()
+            .map_err(|_| InterfaceError::ChipSelectPinError)?;
+        self.write_pin
+            .set_low()
+            .map_err(|_| InterfaceError::WritePinError)?;
+        // The write pulse must be at least 100 ns
+        self.delay.delay_us(1);
+        self.write_pin
+            .set_high()
+            .map_err(|_| InterfaceError::WritePinError)?;
+        self.chip_select_pin
+            .set_high()
+            .map_err(|_| InterfaceError::ChipSelectPinError)
+    }
+
+    /// Sends one register write to the chip
+    fn send(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        self.strobe(false, address)?;
+        // The chip needs 12 cycles after an address write
+        self.delay.delay_us(4);
+        self.strobe(true, value)?;
+        // And 84 cycles after a data write
+        self.delay.delay_us(23);
+
+        Ok(())
+    }
+}
+
+impl<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D> HardwareInterface
+    for ParallelInterface<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D>
+where
+    D0: OutputPin,
+    D1: OutputPin,
+    D2: OutputPin,
+    D3: OutputPin,
+    D4: OutputPin,
+    D5: OutputPin,
+    D6: OutputPin,
+    D7: OutputPin,
+    A: OutputPin,
+    WR: OutputPin,
+    CS: OutputPin,
+    D: DelayUs<u8>,
+{
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        // Set the control pins to their idle level
+        self.write_pin
+            .set_high()
+            .map_err(|_| InterfaceError::WritePinError)?;
+        self.chip_select_pin
+            .set_high()
+            .map_err(|_| InterfaceError::ChipSelectPinError)?;
+
+        // The chip can't be reset from here, so the driver will clear the registers over the bus
+        self.registers = [0; 256];
+
+        Ok(())
+    }
+}
+
+impl<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D> RegisterInterface
+    for ParallelInterface<D0, D1, D2, D3, D4, D5, D6, D7, A, WR, CS, D>
+where
+    D0: OutputPin,
+    D1: OutputPin,
+    D2: OutputPin,
+    D3: OutputPin,
+    D4: OutputPin,
+    D5: OutputPin,
+    D6: OutputPin,
+    D7: OutputPin,
+    A: OutputPin,
+    WR: OutputPin,
+    CS: OutputPin,
+    D: DelayUs<u8>,
+{
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        value
+            .copy_from_slice(&self.registers[(address as usize)..(address as usize + value.len())]);
+        Ok(())
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        for (i, val) in value.iter().enumerate() {
+            self.send(address.wrapping_add(i as u8), *val)?;
+
+            // Save in internal data store once the chip has it, so the image never runs ahead of the chip
+            self.registers[address as usize + i] = *val;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use super::{HardwareInterface, InterfaceError, MemoryInterface, StatusInterface};
+use device_driver::ll::register::RegisterInterface;
+
+/// Gets to see every register write that goes through a [RecordingInterface]
+pub trait WriteRecorder {
+    /// Called for every register that has been written, after the wrapped interface accepted it.
+    ///
+    /// An error is returned from the write, even though the register has been written.
+    fn record(&mut self, address: u8, value: u8) -> Result<(), InterfaceError>;
+
+    /// Called after the chip has been reset
+    fn reset(&mut self) {}
+}
+
+/// Hardware interface wrapper that passes every register write on to a [WriteRecorder].
+///
+/// This is the base of all the interfaces that log, count or analyze the writes of the driver,
+/// like the [crate::ripper::RippingInterface].
+/// Around a [MemoryInterface] it is a chip that only exists in memory.
+pub struct RecordingInterface<I: HardwareInterface, R: WriteRecorder> {
+    interface: I,
+    recorder: R,
+}
+
+impl<I: HardwareInterface, R: WriteRecorder> RecordingInterface<I, R> {
+    pub const fn with_recorder(interface: I, recorder: R) -> Self {
+        Self {
+            interface,
+            recorder,
+        }
+    }
+
+    pub fn recorder(&self) -> &R {
+        &self.recorder
+    }
+
+    pub fn recorder_mut(&mut self) -> &mut R {
+        &mut self.recorder
+    }
+
+    /// Destructs the wrapper into the wrapped interface and the recorder
+    pub fn free(self) -> (I, R) {
+        (self.interface, self.recorder)
+    }
+}
+
+impl<R: WriteRecorder> RecordingInterface<MemoryInterface, R> {
+    /// The current register image
+    pub fn registers(&self) -> &[u8; 256] {
+        self.interface.registers()
+    }
+}
+
+impl<I: HardwareInterface + Default, R: WriteRecorder + Default> Default
+    for RecordingInterface<I, R>
+{
+    fn default() -> Self {
+        Self::with_recorder(I::default(), R::default())
+    }
+}
+
+impl<I: HardwareInterface, R: WriteRecorder> HardwareInterface for RecordingInterface<I, R> {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        self.interface.reset()?;
+        self.recorder.reset();
+        Ok(())
+    }
+
+    fn write_batch(&mut self, writes: &[(u8, u8)]) -> Result<(), InterfaceError> {
+        self.interface.write_batch(writes)?;
+
+        for (address, value) in writes {
+            self.recorder.record(*address, *value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: HardwareInterface, R: WriteRecorder> RegisterInterface for RecordingInterface<I, R> {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.interface.read_register(address, value)
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.interface.write_register(address, value)?;
+
+        for (i, val) in value.iter().enumerate() {
+            self.recorder.record(address.wrapping_add(i as u8), *val)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: StatusInterface, R: WriteRecorder> StatusInterface for RecordingInterface<I, R> {
+    fn read_status(&mut self) -> Result<u8, InterfaceError> {
+        self.interface.read_status()
+    }
+}
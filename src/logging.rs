@@ -0,0 +1,15 @@
+//! Trace logging through the `log` or `defmt` facade, whichever is enabled.
+//!
+//! Without either feature the macros compile to nothing. The arguments must be primitives,
+//! so that they can be formatted by both facades.
+
+macro_rules! trace {
+    ($format:literal $(, $argument:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::trace!($format $(, $argument)*);
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        ::log::trace!($format $(, $argument)*);
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = ($(&$argument,)*);
+    }};
+}
@@ -0,0 +1,216 @@
+//! A register tap that decodes the writes of the driver into changes of the synth state.
+//!
+//! The tap sits between the driver and the hardware interface, so it only knows what the registers say.
+//! It reports levels, frequencies, drums and that an instrument changed, but not which instrument it is,
+//! because the registers don't carry names. Code that needs the names has to track what it loads itself.
+
+use crate::ll::{
+    operator_register_index, ChannelId, HardwareInterface, InterfaceError, Operator,
+    RecordingInterface, WriteRecorder,
+};
+
+/// A drum of the percussion mode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Drum {
+    Bass,
+    Snare,
+    TomTom,
+    Cymbal,
+    HiHat,
+}
+
+impl Drum {
+    /// The drums in the order of their bits in register 0xBD, from the highest bit down
+    const ALL: [Drum; 5] = [
+        Drum::Bass,
+        Drum::Snare,
+        Drum::TomTom,
+        Drum::Cymbal,
+        Drum::HiHat,
+    ];
+
+    /// The bit of the drum in register 0xBD
+    pub(crate) const fn mask(self) -> u8 {
+        match self {
+            Drum::Bass => 0x10,
+            Drum::Snare => 0x08,
+            Drum::TomTom => 0x04,
+            Drum::Cymbal => 0x02,
+            Drum::HiHat => 0x01,
+        }
+    }
+}
+
+/// A change of the synth state that is visible to a player
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Change {
+    /// The channel started playing the frequency
+    KeyOn {
+        channel: ChannelId,
+        frequency_number: u16,
+        block: u8,
+    },
+    /// The channel has been released
+    KeyOff { channel: ChannelId },
+    /// The frequency of a playing channel changed, like during a pitch bend
+    Frequency {
+        channel: ChannelId,
+        frequency_number: u16,
+        block: u8,
+    },
+    /// The output level of the carrier of the channel changed. 0 is the loudest, 63 is silent.
+    Volume { channel: ChannelId, attenuation: u8 },
+    /// Any other setting of the instrument of the channel changed
+    Instrument { channel: ChannelId },
+    /// A drum has been struck or released
+    Drum { drum: Drum, on: bool },
+    /// The chip switched between melody (false) and percussion mode (true)
+    PercussionMode(bool),
+}
+
+/// Hardware interface wrapper that reports every change of the synth state that goes through it.
+///
+/// The writes are decoded into [Change]s, so a display or a remote UI can mirror the synth without
+/// polling the driver or decoding raw register traces.
+/// Writes that don't change anything are not reported.
+/// Like the [crate::ripper::PatchRipper] the decoder keeps its own image of the registers, which takes 256 bytes.
+pub type RegisterTap<I, F> = RecordingInterface<I, ChangeDecoder<F>>;
+
+impl<I: HardwareInterface, F: FnMut(Change)> RegisterTap<I, F> {
+    pub fn new(interface: I, on_change: F) -> Self {
+        Self::with_recorder(interface, ChangeDecoder::new(on_change))
+    }
+}
+
+/// Decodes the register writes of a [RegisterTap] into [Change]s and passes them to the callback
+pub struct ChangeDecoder<F: FnMut(Change)> {
+    registers: [u8; 256],
+    on_change: F,
+}
+
+impl<F: FnMut(Change)> ChangeDecoder<F> {
+    pub fn new(on_change: F) -> Self {
+        Self {
+            registers: [0; 256],
+            on_change,
+        }
+    }
+
+    /// Destructs the decoder into the callback
+    pub fn free(self) -> F {
+        self.on_change
+    }
+
+    fn observe(&mut self, address: u8, value: u8) {
+        let previous = self.registers[address as usize];
+        self.registers[address as usize] = value;
+
+        if previous == value {
+            return;
+        }
+
+        match address {
+            0xA0..=0xA8 | 0xB0..=0xB8 => self.observe_frequency(address, previous),
+            0xBD => {
+                if (previous ^ value) & 0x20 != 0 {
+                    (self.on_change)(Change::PercussionMode(value & 0x20 != 0));
+                }
+
+                for drum in Drum::ALL.iter() {
+                    if (previous ^ value) & drum.mask() != 0 {
+                        (self.on_change)(Change::Drum {
+                            drum: *drum,
+                            on: value & drum.mask() != 0,
+                        });
+                    }
+                }
+            }
+            0xC0..=0xC8 => {
+                let channel = ChannelId::new((address - 0xC0) as usize);
+                self.report_instrument(channel);
+            }
+            0x20..=0x35 | 0x40..=0x55 | 0x60..=0x75 | 0x80..=0x95 | 0xE0..=0xF5 => {
+                let base = address & 0xE0;
+                let offset = (address - base) as usize;
+
+                let carrier_of = (0..ChannelId::COUNT)
+                    .filter_map(ChannelId::new)
+                    .find(|c| operator_register_index(*c, Operator::Carrier) == offset);
+                let modulator_of = (0..ChannelId::COUNT)
+                    .filter_map(ChannelId::new)
+                    .find(|c| operator_register_index(*c, Operator::Modulator) == offset);
+
+                match carrier_of {
+                    Some(channel) if base == 0x40 => {
+                        if (previous ^ value) & 0x3F != 0 {
+                            (self.on_change)(Change::Volume {
+                                channel,
+                                attenuation: value & 0x3F,
+                            });
+                        }
+                        // The key scale level is part of the instrument
+                        if (previous ^ value) & 0xC0 != 0 {
+                            (self.on_change)(Change::Instrument { channel });
+                        }
+                    }
+                    Some(channel) => (self.on_change)(Change::Instrument { channel }),
+                    None => self.report_instrument(modulator_of),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn observe_frequency(&mut self, address: u8, previous: u8) {
+        let channel = match ChannelId::new((address & 0x0F) as usize) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let key_on_register = 0xB0 + channel.index();
+        let key_on = self.registers[key_on_register] & 0x20 != 0;
+        let was_key_on = if address & 0xF0 == 0xB0 {
+            previous & 0x20 != 0
+        } else {
+            key_on
+        };
+
+        let frequency_number = ((self.registers[key_on_register] as u16 & 0x03) << 8)
+            | self.registers[0xA0 + channel.index()] as u16;
+        let block = (self.registers[key_on_register] >> 2) & 0x07;
+
+        let change = match (was_key_on, key_on) {
+            (false, true) => Change::KeyOn {
+                channel,
+                frequency_number,
+                block,
+            },
+            (true, false) => Change::KeyOff { channel },
+            (true, true) => Change::Frequency {
+                channel,
+                frequency_number,
+                block,
+            },
+            // The frequency is set up before the key-on, which reports it
+            (false, false) => return,
+        };
+        (self.on_change)(change);
+    }
+
+    fn report_instrument(&mut self, channel: Option<ChannelId>) {
+        if let Some(channel) = channel {
+            (self.on_change)(Change::Instrument { channel });
+        }
+    }
+}
+
+impl<F: FnMut(Change)> WriteRecorder for ChangeDecoder<F> {
+    fn record(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        self.observe(address, value);
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.registers = [0; 256];
+    }
+}
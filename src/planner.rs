@@ -0,0 +1,91 @@
+use crate::ll::{HardwareInterface, InterfaceError};
+
+/// Gives the order class of a register.
+///
+/// Writing a set of registers is only safe when they are written in this order:
+///
+/// 0. The test register (0x01), so no operation ever runs with test bits set
+/// 1. The timer and note select registers (0x02-0x08)
+/// 2. The operator registers (0x20-0x95 and 0xE0-0xF5)
+/// 3. The channel feedback/connection registers (0xC0-0xC8), so a channel never runs a new
+///    connection with the old operators
+/// 4. The F-number low registers (0xA0-0xA8)
+/// 5. The key-on/block registers (0xB0-0xB8), so a key-on always starts with the full instrument
+///    and frequency in place
+/// 6. The rhythm register (0xBD), because its drum bits key on the rhythm channels
+///
+/// Within a class the writes keep the order in which they were planned.
+const fn write_order(address: u8) -> u8 {
+    match address {
+        0x01 => 0,
+        0x02..=0x08 => 1,
+        0xC0..=0xC8 => 3,
+        0xA0..=0xA8 => 4,
+        0xB0..=0xB8 => 5,
+        0xBD => 6,
+        _ => 2,
+    }
+}
+
+/// Collects a set of register writes and sends them to the chip in the safe order.
+///
+/// Anything that emits a whole set of registers (like an instrument) should go through a planner.
+/// Planning the same address twice only keeps the last value.
+pub(crate) struct WritePlanner<const N: usize> {
+    writes: [(u8, u8); N],
+    len: usize,
+}
+
+impl<const N: usize> WritePlanner<N> {
+    const CAPACITY_CHECK: () = assert!(
+        N <= 256,
+        "A WritePlanner can't plan more writes than there are registers"
+    );
+
+    pub(crate) const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::CAPACITY_CHECK;
+
+        Self {
+            writes: [(0, 0); N],
+            len: 0,
+        }
+    }
+
+    /// Plans a write.
+    ///
+    /// Panics when more than `N` different addresses are planned.
+    /// The driver always plans fixed register sets, so `N` is picked to fit them exactly.
+    pub(crate) fn push(&mut self, address: u8, value: u8) {
+        if let Some(write) = self.writes[..self.len]
+            .iter_mut()
+            .find(|(planned_address, _)| *planned_address == address)
+        {
+            write.1 = value;
+            return;
+        }
+
+        self.writes[self.len] = (address, value);
+        self.len += 1;
+    }
+
+    /// Sends all planned writes to the interface as one batch and empties the plan
+    pub(crate) fn execute<I: HardwareInterface>(
+        &mut self,
+        interface: &mut I,
+    ) -> Result<(), InterfaceError> {
+        let len = core::mem::replace(&mut self.len, 0);
+        let writes = &mut self.writes[..len];
+
+        // Stable so the planned order within a class is kept
+        for i in 1..writes.len() {
+            let mut j = i;
+            while j > 0 && write_order(writes[j - 1].0) > write_order(writes[j].0) {
+                writes.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        interface.write_batch(writes)
+    }
+}
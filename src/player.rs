@@ -0,0 +1,89 @@
+/// A music file format that the chip can play
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    /// DOSBox raw OPL capture
+    Dro,
+    /// id Software music format, as used by Wolfenstein 3D and Commander Keen
+    Imf,
+    /// Video game music log
+    Vgm,
+    /// Creative music file
+    Cmf,
+    /// DMX music format, as used by Doom
+    Mus,
+    /// Reality AdLib Tracker module
+    Rad,
+    /// Standard MIDI file
+    Smf,
+}
+
+/// The amount of IMF records that are checked before the data is believed to be IMF
+const IMF_CHECKED_RECORDS: usize = 64;
+
+/// Finds out the format of the music file from its first bytes.
+///
+/// All formats are recognized by their magic number, except for IMF which doesn't have one.
+/// Data is taken as IMF when it's a whole number of 4 byte records, with or without the length
+/// of the type 1 files in front of it, and the first records only write registers that exist on the chip.
+/// Returns `None` if the format isn't recognized.
+pub fn detect(bytes: &[u8]) -> Option<Format> {
+    const MAGIC_NUMBERS: [(&[u8], Format); 6] = [
+        (b"DBRAWOPL", Format::Dro),
+        (b"Vgm ", Format::Vgm),
+        (b"CTMF", Format::Cmf),
+        (b"MUS\x1A", Format::Mus),
+        (b"RAD by REALiTY!!", Format::Rad),
+        (b"MThd", Format::Smf),
+    ];
+
+    if let Some((_, format)) = MAGIC_NUMBERS
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+    {
+        return Some(*format);
+    }
+
+    let type_1_data = match bytes {
+        [low, high, data @ ..] => {
+            let length = u16::from_le_bytes([*low, *high]) as usize;
+            data.get(..length)
+        }
+        _ => None,
+    };
+
+    if matches!(type_1_data, Some(data) if is_imf_data(data)) || is_imf_data(bytes) {
+        Some(Format::Imf)
+    } else {
+        None
+    }
+}
+
+/// Checks if the data looks like the register writes of an IMF file
+fn is_imf_data(data: &[u8]) -> bool {
+    let records = data.chunks_exact(4);
+    if data.is_empty() || !records.remainder().is_empty() {
+        return false;
+    }
+
+    records
+        .take(IMF_CHECKED_RECORDS)
+        .all(|record| is_register(record[0]))
+}
+
+/// Returns true if the register exists on the chip. 0 is allowed too, because IMF files use it as padding.
+const fn is_register(address: u8) -> bool {
+    matches!(
+        address,
+        0x00..=0x04
+            | 0x08
+            | 0x20..=0x35
+            | 0x40..=0x55
+            | 0x60..=0x75
+            | 0x80..=0x95
+            | 0xA0..=0xA8
+            | 0xB0..=0xB8
+            | 0xBD
+            | 0xC0..=0xC8
+            | 0xE0..=0xF5
+    )
+}
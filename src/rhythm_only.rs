@@ -0,0 +1,148 @@
+use crate::hl::Opl2Error;
+use crate::instrument::{BassDrum, Cymbal, DrumKit, HiHat, MelodyInstrument, SnareDrum, TomTom};
+use crate::ll::{operator_register_index, ChannelId, HardwareInterface, Operator};
+use crate::observer::Drum;
+use crate::tuning::FnumBlock;
+
+/// The rhythm register with only the percussion mode on
+const PERCUSSION_MODE: u8 = 0x20;
+
+/// A small driver for a drum machine that only plays the five drums of the percussion mode.
+///
+/// It only ever writes registers and keeps its own copy of the rhythm register, so it doesn't depend
+/// on the register image of the interface. The melody channels 0-5 are left alone.
+/// For melody and drums together, use [crate::hl::Opl2] in rhythm mode.
+pub struct RhythmOnly<I: HardwareInterface> {
+    interface: I,
+    rhythm: u8,
+}
+
+impl<I: HardwareInterface> RhythmOnly<I> {
+    /// Resets the chip and turns on the percussion mode.
+    ///
+    /// Only the registers that the drums need are written, so this trusts the hardware reset to have
+    /// cleared the others, like [crate::hl::InitConfig::trust_hardware_reset].
+    /// The drums are tuned to C2 for the bass drum and C4 for the others.
+    pub fn initialize(mut interface: I) -> Result<Self, Opl2Error> {
+        interface.reset()?;
+        interface.write_batch(&[(0x01, 0x00), (0x08, 0x00), (0xBD, PERCUSSION_MODE)])?;
+
+        let mut drums = Self {
+            interface,
+            rhythm: PERCUSSION_MODE,
+        };
+        drums.tune(Drum::Bass, FnumBlock::new(0x157, 2))?;
+        drums.tune(Drum::Snare, FnumBlock::new(0x157, 4))?;
+        drums.tune(Drum::TomTom, FnumBlock::new(0x157, 4))?;
+        trace!("rhythm only driver initialized");
+
+        Ok(drums)
+    }
+
+    pub fn free(self) -> I {
+        self.interface
+    }
+
+    /// Sets the pitch of the channel the drum is played on.
+    ///
+    /// The snare drum shares its channel with the hi-hat and the tom-tom with the cymbal,
+    /// so tuning one also tunes the other.
+    pub fn tune(&mut self, drum: Drum, pitch: FnumBlock) -> Result<(), Opl2Error> {
+        let channel = match drum {
+            Drum::Bass => ChannelId::BASS_DRUM,
+            Drum::Snare | Drum::HiHat => ChannelId::SNARE_DRUM_HI_HAT,
+            Drum::TomTom | Drum::Cymbal => ChannelId::TOM_TOM_CYMBAL,
+        };
+        let index = channel.index() as u8;
+
+        // The key-on bit stays off, the drums are keyed by the rhythm register
+        self.interface.write_batch(&[
+            (0xA0 + index, (pitch.fnum & 0xFF) as u8),
+            (
+                0xB0 + index,
+                ((pitch.fnum >> 8) & 0x03) as u8 | (pitch.block & 0x07) << 2,
+            ),
+        ])?;
+
+        Ok(())
+    }
+
+    pub fn setup_bass_drum(&mut self, value: BassDrum) -> Result<(), Opl2Error> {
+        let mut writes = [(0, 0); 11];
+        let instrument = MelodyInstrument::from_bytes(value.to_bytes());
+        for (write, register) in writes
+            .iter_mut()
+            .zip(instrument.register_writes(BassDrum::CHANNEL))
+        {
+            *write = register;
+        }
+
+        self.interface.write_batch(&writes)?;
+        Ok(())
+    }
+
+    pub fn setup_snare_drum(&mut self, value: SnareDrum) -> Result<(), Opl2Error> {
+        self.setup_operator(SnareDrum::CHANNEL, SnareDrum::OPERATOR, value.to_bytes())
+    }
+
+    pub fn setup_tom_tom(&mut self, value: TomTom) -> Result<(), Opl2Error> {
+        self.setup_operator(TomTom::CHANNEL, TomTom::OPERATOR, value.to_bytes())
+    }
+
+    pub fn setup_cymbal(&mut self, value: Cymbal) -> Result<(), Opl2Error> {
+        self.setup_operator(Cymbal::CHANNEL, Cymbal::OPERATOR, value.to_bytes())
+    }
+
+    pub fn setup_hi_hat(&mut self, value: HiHat) -> Result<(), Opl2Error> {
+        self.setup_operator(HiHat::CHANNEL, HiHat::OPERATOR, value.to_bytes())
+    }
+
+    /// Sets up all five drums at once
+    pub fn setup_drum_kit(&mut self, value: DrumKit) -> Result<(), Opl2Error> {
+        self.setup_bass_drum(value.bass_drum)?;
+        self.setup_snare_drum(value.snare_drum)?;
+        self.setup_tom_tom(value.tom_tom)?;
+        self.setup_cymbal(value.cymbal)?;
+        self.setup_hi_hat(value.hi_hat)
+    }
+
+    /// Turns the drum on or off
+    pub fn set(&mut self, drum: Drum, on: bool) -> Result<(), Opl2Error> {
+        if on {
+            self.rhythm |= drum.mask();
+        } else {
+            self.rhythm &= !drum.mask();
+        }
+
+        self.interface.write_batch(&[(0xBD, self.rhythm)])?;
+        Ok(())
+    }
+
+    /// Plays the drum from the start, also when it was still sounding
+    pub fn hit(&mut self, drum: Drum) -> Result<(), Opl2Error> {
+        let off = self.rhythm & !drum.mask();
+        self.rhythm = off | drum.mask();
+
+        self.interface
+            .write_batch(&[(0xBD, off), (0xBD, self.rhythm)])?;
+        Ok(())
+    }
+
+    fn setup_operator(
+        &mut self,
+        channel: ChannelId,
+        operator: Operator,
+        bytes: [u8; 5],
+    ) -> Result<(), Opl2Error> {
+        let offset = operator_register_index(channel, operator) as u8;
+
+        self.interface.write_batch(&[
+            (0x20 + offset, bytes[0]),
+            (0x40 + offset, bytes[1]),
+            (0x60 + offset, bytes[2]),
+            (0x80 + offset, bytes[3]),
+            (0xE0 + offset, bytes[4]),
+        ])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::instrument::{ibk, InstrumentBank, MelodyInstrument};
+use crate::ll::{
+    operator_register_index, ChannelId, HardwareInterface, InterfaceError, Operator,
+    RecordingInterface, WriteRecorder,
+};
+
+/// Size of an exported SBI file
+pub const SBI_SIZE: usize = 52;
+/// Size of an exported IBK bank
+pub const IBK_SIZE: usize = InstrumentBank::FILE_SIZE;
+
+/// Collects every unique melody instrument that is played on the chip.
+///
+/// The ripper keeps its own image of the registers and takes a snapshot of the channel's instrument every time
+/// a channel goes from key-off to key-on. Snapshots that are already in the bank are ignored.
+/// Channels 6-8 are skipped while the chip is in percussion mode.
+///
+/// The bank can hold `N` instruments. Any new instruments beyond that are dropped.
+/// The ripper takes `256 + 11 * N` bytes of RAM and `N` must be at least 1.
+pub struct PatchRipper<const N: usize> {
+    registers: [u8; 256],
+    patches: [[u8; 11]; N],
+    patch_count: usize,
+}
+
+impl<const N: usize> PatchRipper<N> {
+    const CAPACITY_CHECK: () = assert!(N > 0, "A PatchRipper must be able to hold an instrument");
+
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::CAPACITY_CHECK;
+
+        Self {
+            registers: [0; 256],
+            patches: [[0; 11]; N],
+            patch_count: 0,
+        }
+    }
+
+    /// Feed a single register write of a trace to the ripper
+    pub fn observe(&mut self, address: u8, value: u8) {
+        let previous = self.registers[address as usize];
+        self.registers[address as usize] = value;
+
+        if let Some(channel) = address
+            .checked_sub(0xB0)
+            .and_then(|channel| ChannelId::new(channel as usize))
+        {
+            let key_on = |v: u8| v & 0x20 != 0;
+            let percussion_mode = self.registers[0xBD] & 0x20 != 0;
+
+            if key_on(value) && !key_on(previous) && !(percussion_mode && channel.index() >= 6) {
+                self.rip_channel(channel);
+            }
+        }
+    }
+
+    /// Forget the register image and all collected instruments
+    pub fn clear(&mut self) {
+        self.registers = [0; 256];
+        self.patch_count = 0;
+    }
+
+    fn rip_channel(&mut self, channel: ChannelId) {
+        let modulator = operator_register_index(channel, Operator::Modulator);
+        let carrier = operator_register_index(channel, Operator::Carrier);
+        let channel = channel.index();
+        let r = &self.registers;
+
+        let patch = [
+            r[0x20 + modulator],
+            r[0x40 + modulator],
+            r[0x60 + modulator],
+            r[0x80 + modulator],
+            r[0xE0 + modulator],
+            r[0xC0 + channel],
+            r[0x20 + carrier],
+            r[0x40 + carrier],
+            r[0x60 + carrier],
+            r[0x80 + carrier],
+            r[0xE0 + carrier],
+        ];
+
+        if self.patches[..self.patch_count].contains(&patch) || self.patch_count == N {
+            return;
+        }
+
+        self.patches[self.patch_count] = patch;
+        self.patch_count += 1;
+    }
+
+    /// The amount of unique instruments that have been collected
+    pub fn len(&self) -> usize {
+        self.patch_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patch_count == 0
+    }
+
+    /// Get the collected instrument at the index
+    pub fn get(&self, index: usize) -> Option<MelodyInstrument> {
+        self.patches[..self.patch_count]
+            .get(index)
+            .map(|patch| MelodyInstrument::from_bytes(*patch))
+    }
+
+    /// Iterate over all collected instruments in the order they were first heard
+    pub fn instruments(&self) -> impl Iterator<Item = MelodyInstrument> + '_ {
+        self.patches[..self.patch_count]
+            .iter()
+            .map(|patch| MelodyInstrument::from_bytes(*patch))
+    }
+
+    /// Export the collected instrument at the index as an SBI file.
+    ///
+    /// The name is truncated to 31 bytes.
+    pub fn sbi(&self, index: usize, name: &str) -> Option<[u8; SBI_SIZE]> {
+        let patch = self.patches[..self.patch_count].get(index)?;
+
+        let mut sbi = [0; SBI_SIZE];
+        sbi[0..4].copy_from_slice(b"SBI\x1A");
+        let name_len = name.len().min(31);
+        sbi[4..4 + name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+        sbi[36..52].copy_from_slice(&MelodyInstrument::from_bytes(*patch).to_sbi_record());
+
+        Some(sbi)
+    }
+
+    /// Export the first 128 collected instruments as an IBK bank.
+    ///
+    /// The instruments are named `RIP000` through `RIP127`. Unused entries are left empty.
+    pub fn write_ibk(&self, buffer: &mut [u8; IBK_SIZE]) {
+        *buffer = [0; IBK_SIZE];
+        buffer[0..4].copy_from_slice(ibk::MAGIC);
+
+        for (i, instrument) in self.instruments().take(InstrumentBank::LEN).enumerate() {
+            let data_start = 4 + i * ibk::RECORD_SIZE;
+            buffer[data_start..data_start + ibk::RECORD_SIZE]
+                .copy_from_slice(&instrument.to_sbi_record());
+
+            let name_start = 4 + InstrumentBank::LEN * ibk::RECORD_SIZE + i * ibk::NAME_SIZE;
+            buffer[name_start..name_start + 6].copy_from_slice(&[
+                b'R',
+                b'I',
+                b'P',
+                b'0' + (i / 100) as u8,
+                b'0' + (i / 10 % 10) as u8,
+                b'0' + (i % 10) as u8,
+            ]);
+        }
+    }
+}
+
+impl<const N: usize> Default for PatchRipper<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> WriteRecorder for PatchRipper<N> {
+    fn record(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        self.observe(address, value);
+        Ok(())
+    }
+
+    /// The chip has forgotten its registers, the collected instruments are kept
+    fn reset(&mut self) {
+        self.registers = [0; 256];
+    }
+}
+
+/// Hardware interface wrapper that rips all instruments that are played through it
+pub type RippingInterface<I, const N: usize> = RecordingInterface<I, PatchRipper<N>>;
+
+impl<I: HardwareInterface, const N: usize> RippingInterface<I, N> {
+    pub const fn new(interface: I) -> Self {
+        Self::with_recorder(interface, PatchRipper::new())
+    }
+
+    pub fn ripper(&self) -> &PatchRipper<N> {
+        self.recorder()
+    }
+}
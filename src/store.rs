@@ -0,0 +1,196 @@
+use crate::instrument::MelodyInstrument;
+
+/// Size of a serialized patch record
+pub const PATCH_RECORD_SIZE: usize = 16;
+
+/// Marks a record as holding a patch.
+///
+/// Erased flash reads as `0xFF` and a deleted record has its marker cleared to `0x00`.
+/// The delete only writes that single byte, but `embedded_storage::Storage` is free to do it as a
+/// read-modify-write of the whole erase block. Whether it costs an erase cycle is up to the storage.
+const RECORD_MARKER: u8 = 0xA5;
+const RECORD_VERSION: u8 = 1;
+
+/// Serializes a user patch into a record.
+///
+/// Layout: marker, version, slot, the 11 instrument bytes as [MelodyInstrument::from_bytes] takes them
+/// and a 16-bit Fletcher checksum over everything before it.
+pub fn encode_patch_record(slot: u8, instrument: &MelodyInstrument) -> [u8; PATCH_RECORD_SIZE] {
+    let mut record = [0; PATCH_RECORD_SIZE];
+    record[0] = RECORD_MARKER;
+    record[1] = RECORD_VERSION;
+    record[2] = slot;
+    record[3..14].copy_from_slice(&instrument.to_bytes());
+    let checksum = fletcher16(&record[..14]);
+    record[14..16].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+/// Deserializes a record into its slot and patch.
+///
+/// Returns `None` for empty, deleted, damaged or unknown records.
+pub fn decode_patch_record(record: &[u8; PATCH_RECORD_SIZE]) -> Option<(u8, MelodyInstrument)> {
+    if record[0] != RECORD_MARKER || record[1] != RECORD_VERSION {
+        return None;
+    }
+
+    if u16::from_le_bytes([record[14], record[15]]) != fletcher16(&record[..14]) {
+        return None;
+    }
+
+    let mut patch = [0; 11];
+    patch.copy_from_slice(&record[3..14]);
+    Some((record[2], MelodyInstrument::from_bytes(patch)))
+}
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let (sum1, sum2) = data.iter().fold((0u16, 0u16), |(sum1, sum2), byte| {
+        let sum1 = (sum1 + *byte as u16) % 255;
+        (sum1, (sum2 + sum1) % 255)
+    });
+
+    (sum2 << 8) | sum1
+}
+
+/// A set of patch slots
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SlotSet([u32; 8]);
+
+impl SlotSet {
+    pub const fn new() -> Self {
+        Self([0; 8])
+    }
+
+    pub fn insert(&mut self, slot: u8) {
+        self.0[slot as usize / 32] |= 1 << (slot % 32);
+    }
+
+    pub fn remove(&mut self, slot: u8) {
+        self.0[slot as usize / 32] &= !(1 << (slot % 32));
+    }
+
+    pub fn contains(&self, slot: u8) -> bool {
+        self.0[slot as usize / 32] & (1 << (slot % 32)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    /// Iterate over the slots in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=255u8).filter(move |slot| self.contains(*slot))
+    }
+}
+
+/// Persistent storage for user patches, addressed by slot number
+pub trait PatchStore {
+    type Error;
+
+    /// Stores the patch in the slot, replacing what was there before
+    fn save(&mut self, slot: u8, instrument: &MelodyInstrument) -> Result<(), Self::Error>;
+    /// Loads the patch in the slot or `None` if the slot is empty
+    fn load(&mut self, slot: u8) -> Result<Option<MelodyInstrument>, Self::Error>;
+    /// Makes the slot empty
+    fn delete(&mut self, slot: u8) -> Result<(), Self::Error>;
+    /// Gives all slots that hold a patch
+    fn list(&mut self) -> Result<SlotSet, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum StoreError<E> {
+    InvalidSlot,
+    StorageError(E),
+}
+
+/// A [PatchStore] on top of an `embedded-storage` storage.
+///
+/// Slots are stored as consecutive [PATCH_RECORD_SIZE] byte records starting at the offset.
+/// To spare the flash, a save that wouldn't change the record and a delete of an empty slot don't write anything.
+#[cfg(feature = "embedded-storage")]
+pub struct StoragePatchStore<S: embedded_storage::Storage> {
+    storage: S,
+    offset: u32,
+    slot_count: u16,
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<S: embedded_storage::Storage> StoragePatchStore<S> {
+    /// Creates a new store that uses `slot_count * PATCH_RECORD_SIZE` bytes of the storage from the offset
+    pub fn new(storage: S, offset: u32, slot_count: u16) -> Self {
+        Self {
+            storage,
+            offset,
+            slot_count: slot_count.min(256),
+        }
+    }
+
+    pub fn free(self) -> S {
+        self.storage
+    }
+
+    fn record_address(&self, slot: u8) -> Result<u32, StoreError<S::Error>> {
+        if slot as u16 >= self.slot_count {
+            return Err(StoreError::InvalidSlot);
+        }
+
+        Ok(self.offset + slot as u32 * PATCH_RECORD_SIZE as u32)
+    }
+
+    fn read_record(&mut self, slot: u8) -> Result<[u8; PATCH_RECORD_SIZE], StoreError<S::Error>> {
+        let address = self.record_address(slot)?;
+        let mut record = [0; PATCH_RECORD_SIZE];
+        self.storage
+            .read(address, &mut record)
+            .map_err(StoreError::StorageError)?;
+        Ok(record)
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<S: embedded_storage::Storage> PatchStore for StoragePatchStore<S> {
+    type Error = StoreError<S::Error>;
+
+    fn save(&mut self, slot: u8, instrument: &MelodyInstrument) -> Result<(), Self::Error> {
+        let record = encode_patch_record(slot, instrument);
+        if self.read_record(slot)? == record {
+            return Ok(());
+        }
+
+        let address = self.record_address(slot)?;
+        self.storage
+            .write(address, &record)
+            .map_err(StoreError::StorageError)
+    }
+
+    fn load(&mut self, slot: u8) -> Result<Option<MelodyInstrument>, Self::Error> {
+        Ok(match decode_patch_record(&self.read_record(slot)?) {
+            Some((record_slot, instrument)) if record_slot == slot => Some(instrument),
+            _ => None,
+        })
+    }
+
+    fn delete(&mut self, slot: u8) -> Result<(), Self::Error> {
+        if self.read_record(slot)?[0] != RECORD_MARKER {
+            return Ok(());
+        }
+
+        let address = self.record_address(slot)?;
+        self.storage
+            .write(address, &[0x00])
+            .map_err(StoreError::StorageError)
+    }
+
+    fn list(&mut self) -> Result<SlotSet, Self::Error> {
+        let mut slots = SlotSet::new();
+
+        for slot in 0..self.slot_count {
+            let slot = slot as u8;
+            if self.load(slot)?.is_some() {
+                slots.insert(slot);
+            }
+        }
+
+        Ok(slots)
+    }
+}
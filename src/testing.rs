@@ -0,0 +1,227 @@
+use crate::entropy::{Entropy, XorShift};
+use crate::hl::Opl2;
+use crate::ll::{
+    HardwareInterface, InterfaceError, MemoryInterface, RecordingInterface, StatusInterface,
+    WriteRecorder,
+};
+use device_driver::ll::register::RegisterInterface;
+
+/// Interface wrapper that injects errors into the writes, so error handling and recovery code can be tested.
+///
+/// A failed write is not passed on to the wrapped interface. Every register of a batch counts as its own write.
+pub struct FaultyInterface<I: HardwareInterface> {
+    interface: I,
+    error: InterfaceError,
+    write_count: usize,
+    fail_on_write: Option<usize>,
+    failure_chance_per_mille: u16,
+    random: XorShift,
+    failure_count: usize,
+}
+
+impl<I: HardwareInterface> FaultyInterface<I> {
+    /// Wraps the interface. No errors are injected until configured.
+    pub fn new(interface: I) -> Self {
+        Self {
+            interface,
+            error: InterfaceError::CommunicationError,
+            write_count: 0,
+            fail_on_write: None,
+            failure_chance_per_mille: 0,
+            random: XorShift::new(0x1234_5678),
+            failure_count: 0,
+        }
+    }
+
+    /// Sets the error that is injected. The default is a communication error.
+    pub fn set_error(&mut self, error: InterfaceError) {
+        self.error = error;
+    }
+
+    /// Makes the nth write call from now on fail (1 is the next one)
+    pub fn fail_on_write(&mut self, n: usize) {
+        self.fail_on_write = Some(self.write_count + n);
+    }
+
+    /// Makes every write call fail with the given chance.
+    ///
+    /// The same seed always gives the same failures.
+    pub fn fail_randomly(&mut self, chance_per_mille: u16, seed: u32) {
+        self.failure_chance_per_mille = chance_per_mille;
+        self.random = XorShift::new(seed);
+    }
+
+    /// The amount of errors that have been injected
+    pub fn failure_count(&self) -> usize {
+        self.failure_count
+    }
+
+    pub fn free(self) -> I {
+        self.interface
+    }
+}
+
+impl<I: HardwareInterface> HardwareInterface for FaultyInterface<I> {
+    fn reset(&mut self) -> Result<(), InterfaceError> {
+        self.interface.reset()
+    }
+}
+
+impl<I: HardwareInterface> RegisterInterface for FaultyInterface<I> {
+    type Address = u8;
+    type InterfaceError = InterfaceError;
+
+    fn read_register(
+        &mut self,
+        address: Self::Address,
+        value: &mut [u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.interface.read_register(address, value)
+    }
+
+    fn write_register(
+        &mut self,
+        address: Self::Address,
+        value: &[u8],
+    ) -> Result<(), Self::InterfaceError> {
+        self.write_count += 1;
+
+        let nth_write_fails = self.fail_on_write == Some(self.write_count);
+        let random_fail = self.failure_chance_per_mille > 0
+            && self.random.next_below(1000) < self.failure_chance_per_mille as u32;
+
+        if nth_write_fails || random_fail {
+            self.failure_count += 1;
+            return Err(self.error);
+        }
+
+        self.interface.write_register(address, value)
+    }
+}
+
+/// A register write as seen by the [MockInterface]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LoggedWrite {
+    /// The virtual time of the write
+    pub time_us: u64,
+    pub address: u8,
+    pub value: u8,
+}
+
+/// A chip in memory that logs every register write, to test the exact register sequences of the driver.
+///
+/// The log is kept by the [WriteLog] recorder, which is reached through [RecordingInterface::recorder].
+pub type MockInterface<const N: usize> = RecordingInterface<MemoryInterface, WriteLog<N>>;
+
+impl<const N: usize> MockInterface<N> {
+    pub const fn new() -> Self {
+        Self::with_recorder(MemoryInterface::new(), WriteLog::new())
+    }
+}
+
+impl<const N: usize, STATE> Opl2<MockInterface<N>, STATE> {
+    /// The write log of the mock, to check and clear what the driver wrote
+    pub fn write_log(&mut self) -> &mut WriteLog<N> {
+        self.interface_mut().recorder_mut()
+    }
+}
+
+impl<const N: usize> StatusInterface for MockInterface<N> {
+    fn read_status(&mut self) -> Result<u8, InterfaceError> {
+        Ok(self.recorder().status)
+    }
+}
+
+/// The write log of the [MockInterface].
+///
+/// The time is virtual and only moves with [Self::advance], so the logs are the same on every run.
+/// The log holds `N` writes. Writes beyond that still reach the register image, but are only counted.
+pub struct WriteLog<const N: usize> {
+    log: [LoggedWrite; N],
+    write_count: usize,
+    reset_count: usize,
+    time_us: u64,
+    status: u8,
+}
+
+impl<const N: usize> WriteLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            log: [LoggedWrite {
+                time_us: 0,
+                address: 0,
+                value: 0,
+            }; N],
+            write_count: 0,
+            reset_count: 0,
+            time_us: 0,
+            status: 0,
+        }
+    }
+
+    /// Moves the virtual time forward
+    pub fn advance(&mut self, us: u64) {
+        self.time_us += us;
+    }
+
+    /// Sets what the status register reads as
+    pub fn set_status(&mut self, status: u8) {
+        self.status = status;
+    }
+
+    /// The logged writes in the order they were done
+    pub fn writes(&self) -> &[LoggedWrite] {
+        &self.log[..self.write_count.min(N)]
+    }
+
+    /// The logged writes as (address, value) pairs
+    pub fn register_writes(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.writes()
+            .iter()
+            .map(|write| (write.address, write.value))
+    }
+
+    /// Returns true if exactly these (address, value) pairs have been written since the log was cleared
+    pub fn wrote(&self, expected: &[(u8, u8)]) -> bool {
+        self.write_count == expected.len() && self.register_writes().eq(expected.iter().copied())
+    }
+
+    /// The amount of writes since the log was cleared, including the ones that didn't fit in the log
+    pub fn write_count(&self) -> usize {
+        self.write_count
+    }
+
+    /// The amount of times the chip has been reset
+    pub fn reset_count(&self) -> usize {
+        self.reset_count
+    }
+
+    /// Empties the log, but keeps the time
+    pub fn clear_log(&mut self) {
+        self.write_count = 0;
+    }
+}
+
+impl<const N: usize> Default for WriteLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> WriteRecorder for WriteLog<N> {
+    fn record(&mut self, address: u8, value: u8) -> Result<(), InterfaceError> {
+        if let Some(entry) = self.log.get_mut(self.write_count) {
+            *entry = LoggedWrite {
+                time_us: self.time_us,
+                address,
+                value,
+            };
+        }
+        self.write_count += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reset_count += 1;
+    }
+}
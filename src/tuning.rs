@@ -0,0 +1,334 @@
+use crate::hl::{Initialized, Note, Opl2, Opl2Error};
+use crate::instrument::MelodyInstrument;
+use crate::ll::HardwareInterface;
+
+/// The note that [play_calibration_tone] plays
+pub const CALIBRATION_NOTE: Note = Note::A(4);
+
+/// A pure sine: the modulator is fully attenuated and the carrier sustains at full volume as long as the key is on
+const CALIBRATION_INSTRUMENT: MelodyInstrument = MelodyInstrument::from_bytes([
+    0x21, 0x3F, 0xF0, 0x00, 0x00, 0x00, 0x21, 0x00, 0xF0, 0x05, 0x00,
+]);
+
+/// The clock frequency the chip runs on.
+///
+/// The tuning of all notes depends on it. Boards with an off-spec crystal can measure the real clock
+/// with [play_calibration_tone] and [ChipClock::from_calibration] and store the result.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChipClock {
+    hz: u32,
+}
+
+impl ChipClock {
+    /// The clock of the datasheet
+    pub const NOMINAL: Self = Self::from_hz(3_579_545);
+
+    pub const fn from_hz(hz: u32) -> Self {
+        Self { hz }
+    }
+
+    pub const fn hz(&self) -> u32 {
+        self.hz
+    }
+
+    /// Computes the clock from the frequency of the calibration tone, as measured by a frequency counter or a tuner.
+    pub fn from_calibration(measured_millihertz: u32) -> Self {
+        let (frequency_number, block) = Self::calibration_registers();
+
+        // The chip plays `clock / 72 * frequency_number / 2^(20 - block)` Hz
+        let hz = measured_millihertz as u64 * 72 * (1 << (20 - block)) / 1000 / frequency_number;
+        Self::from_hz(hz as u32)
+    }
+
+    /// The frequency that the calibration tone has with this clock
+    pub fn calibration_tone_millihertz(&self) -> u32 {
+        let (frequency_number, block) = Self::calibration_registers();
+        self.millihertz(frequency_number as u16, block)
+    }
+
+    /// The frequency that the chip plays with the frequency number (0-1023) and block (0-7) on this clock
+    pub fn millihertz(&self, frequency_number: u16, block: u8) -> u32 {
+        (self.hz as u64 * 1000 * frequency_number as u64 / 72 / (1 << (20 - block.min(7)))) as u32
+    }
+
+    /// Gives the frequency number and block that play the frequency the most precise with this clock.
+    ///
+    /// Returns `None` if the frequency is too high for the chip, or if the clock is 0 Hz and plays nothing at all.
+    pub fn frequency_number(&self, millihertz: u32) -> Option<(u16, u8)> {
+        if self.hz == 0 {
+            return None;
+        }
+
+        (0..=7).find_map(|block| {
+            let divider = self.hz as u64 * 1000;
+            let frequency_number =
+                (millihertz as u64 * 72 * (1 << (20 - block)) + divider / 2) / divider;
+
+            if frequency_number < 1024 {
+                Some((frequency_number as u16, block))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Same as [Self::frequency_number], but the frequencies that are too high for the chip are played as high as it goes
+    pub fn saturating_frequency_number(&self, millihertz: u32) -> (u16, u8) {
+        self.frequency_number(millihertz).unwrap_or((1023, 7))
+    }
+
+    fn calibration_registers() -> (u64, u8) {
+        (
+            CALIBRATION_NOTE.get_frequency() as u64,
+            CALIBRATION_NOTE.get_octave(),
+        )
+    }
+}
+
+impl Default for ChipClock {
+    fn default() -> Self {
+        Self::NOMINAL
+    }
+}
+
+/// The frequency ratios of the semitones of an octave in 16.16 fixed point
+const SEMITONE_RATIOS: [u64; 12] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715,
+];
+
+/// The frequency number (0-1023) and block (0-7) the chip plays a frequency with
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FnumBlock {
+    pub fnum: u16,
+    pub block: u8,
+}
+
+impl FnumBlock {
+    pub const fn new(fnum: u16, block: u8) -> Self {
+        Self { fnum, block }
+    }
+
+    /// The registers that play the frequency the most precise with this clock.
+    ///
+    /// A board that runs the chip on another crystal than the nominal 3.58 MHz only needs its own [ChipClock]
+    /// to be in tune. Returns `None` if the frequency is too high for the chip.
+    pub fn from_millihertz(millihertz: u32, clock: &ChipClock) -> Option<Self> {
+        let (fnum, block) = clock.frequency_number(millihertz)?;
+        Some(Self::new(fnum, block))
+    }
+
+    /// Same as [Self::from_millihertz], for whole hertz
+    pub fn from_hz(hz: u32, clock: &ChipClock) -> Option<Self> {
+        Self::from_millihertz(hz.saturating_mul(1000), clock)
+    }
+
+    /// The registers for the note in equal temperament with A4 at 440 Hz.
+    ///
+    /// Unlike the frequency numbers of [Note::get_frequency], which are made for the nominal clock,
+    /// this is in tune on any clock.
+    pub fn from_note(note: Note, clock: &ChipClock) -> Option<Self> {
+        Self::from_millihertz(Pitch::of_note(note).millihertz(), clock)
+    }
+
+    /// The frequency the chip plays with these registers on the clock
+    pub fn pitch(&self, clock: &ChipClock) -> Pitch {
+        Pitch::from_millihertz(clock.millihertz(self.fnum, self.block))
+    }
+
+    /// The registers for the MIDI note number (0-127) with this clock.
+    ///
+    /// Returns `None` for the notes that are too high for the chip, which are those above about
+    /// F#8 (114) with the nominal clock.
+    pub fn from_midi(note: u8, clock: &ChipClock) -> Option<Self> {
+        Self::from_millihertz(Pitch::of_midi(note.min(127)).millihertz(), clock)
+    }
+}
+
+/// A frequency with millihertz resolution
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Pitch {
+    millihertz: u32,
+}
+
+impl Pitch {
+    /// The frequencies of octave 4 in equal temperament with A4 at 440 Hz, starting at C
+    const OCTAVE_4_MILLIHERTZ: [u32; 12] = [
+        261_626, 277_183, 293_665, 311_127, 329_628, 349_228, 369_994, 391_995, 415_305, 440_000,
+        466_164, 493_883,
+    ];
+
+    pub const fn from_millihertz(millihertz: u32) -> Self {
+        Self { millihertz }
+    }
+
+    pub const fn from_hz(hz: u32) -> Self {
+        Self::from_millihertz(hz * 1000)
+    }
+
+    pub const fn millihertz(&self) -> u32 {
+        self.millihertz
+    }
+
+    /// The exact pitch of the note in equal temperament with A4 at 440 Hz
+    pub fn of_note(note: Note) -> Self {
+        let octave_4 = Self::OCTAVE_4_MILLIHERTZ[note.semitone() as usize] as u64;
+        Self::from_millihertz((octave_4 * (1 << note.get_octave()) / 16) as u32)
+    }
+
+    /// The pitch that is the amount of cents higher (or lower) than this one
+    pub fn transposed(&self, cents: i64) -> Self {
+        let octaves = cents.div_euclid(1200);
+        let semitones = cents.rem_euclid(1200) / 100;
+        let cents = cents.rem_euclid(100);
+
+        let mut millihertz = (self.millihertz as u64 * SEMITONE_RATIOS[semitones as usize]) >> 16;
+
+        // 2^(cents / 1200) ≈ 1 + x + x²/2 with x = cents * ln(2) / 1200, in parts per million
+        let x = cents as u64 * 577_623 / 1000;
+        millihertz = millihertz * (1_000_000 + x + x * x / 2_000_000) / 1_000_000;
+
+        let millihertz = if octaves >= 0 {
+            // Anything above 20 octaves is out of range anyway
+            millihertz << octaves.min(20)
+        } else {
+            millihertz.checked_shr(-octaves as u32).unwrap_or(0)
+        };
+        Self::from_millihertz(millihertz.min(u32::MAX as u64) as u32)
+    }
+
+    /// The exact pitch of the MIDI note number in equal temperament with A4 (69) at 440 Hz
+    pub fn of_midi(note: u8) -> Self {
+        let octave_4 = Self::OCTAVE_4_MILLIHERTZ[(note % 12) as usize] as u64;
+        // MIDI octave 5 is octave 4 of the chip
+        Self::from_millihertz((octave_4 * (1 << (note / 12)) / 32) as u32)
+    }
+
+    /// Finds the note that is closest to the pitch and how many cents the pitch is above (or below) it.
+    ///
+    /// Returns `None` if the pitch is more than half a semitone outside of the octaves 0-7.
+    pub fn nearest_note(&self) -> Option<(Note, i16)> {
+        let mut nearest: Option<(Note, i16)> = None;
+
+        for octave in 0..=7 {
+            for semitone in 0..12 {
+                // Can't fail, the semitone is in range
+                let note = Note::from_semitone(semitone, octave)?;
+                let cents = Self::cents_between(Self::of_note(note), *self);
+
+                match nearest {
+                    Some((_, nearest_cents)) if nearest_cents.abs() <= cents.abs() => {}
+                    _ => nearest = Some((note, cents)),
+                }
+            }
+        }
+
+        nearest.filter(|(_, cents)| cents.abs() <= 50)
+    }
+
+    /// The pitch of the nearest note, for when the input has to be quantized to the scale
+    pub fn snap(&self) -> Option<Self> {
+        self.nearest_note().map(|(note, _)| Self::of_note(note))
+    }
+
+    /// The distance in cents from the reference to the pitch.
+    ///
+    /// Uses `1200 * log2(p / r) ≈ 3462 * (p - r) / (p + r)`, which is accurate to a fraction of a cent
+    /// within a semitone. Further away it's too small, but it still goes up with the distance.
+    fn cents_between(reference: Self, pitch: Self) -> i16 {
+        let reference = reference.millihertz as i64;
+        let pitch = pitch.millihertz as i64;
+
+        (3462 * (pitch - reference) / (pitch + reference).max(1)) as i16
+    }
+}
+
+/// Shows the pitch in hertz with three decimals, like `440.000 Hz`
+impl core::fmt::Display for Pitch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}.{:03} Hz",
+            self.millihertz / 1000,
+            self.millihertz % 1000
+        )
+    }
+}
+
+/// Starts the channel on the pitch, either exactly or snapped to the nearest note.
+///
+/// Calling it again while the channel plays only changes the pitch, so the pitch can be followed continuously.
+pub fn start_pitch<I: HardwareInterface, INIT: Initialized>(
+    opl: &mut Opl2<I, INIT>,
+    channel: usize,
+    pitch: Pitch,
+    snap: bool,
+    clock: &ChipClock,
+) -> Result<(), Opl2Error> {
+    let pitch = if snap {
+        pitch.snap().unwrap_or(pitch)
+    } else {
+        pitch
+    };
+
+    let (frequency_number, block) = clock.saturating_frequency_number(pitch.millihertz());
+    opl.start_channel_frequency(channel, frequency_number, block)
+}
+
+/// Sets up a pure sine on the channel and keeps playing the [CALIBRATION_NOTE] until the channel is stopped.
+///
+/// Measure the tone and pass the result to [ChipClock::from_calibration].
+/// With the nominal clock the tone is about 437.7 Hz.
+pub fn play_calibration_tone<I: HardwareInterface, INIT: Initialized>(
+    opl: &mut Opl2<I, INIT>,
+    channel: usize,
+) -> Result<(), Opl2Error> {
+    opl.setup_melody_instrument(channel, CALIBRATION_INSTRUMENT)?;
+    opl.start_channel(channel, CALIBRATION_NOTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency_number_round_trips() {
+        for clock in [
+            ChipClock::NOMINAL,
+            ChipClock::from_hz(3_600_000),
+            ChipClock::from_hz(14_318_180),
+        ]
+        .iter()
+        {
+            for hz in (20..6_000).step_by(7) {
+                let millihertz = hz * 1000 + 123;
+                let (frequency_number, block) = clock.frequency_number(millihertz).unwrap();
+                assert!(frequency_number < 1024 && block < 8);
+
+                // Rounded to the closest frequency number, so off by less than one step
+                let played = clock.millihertz(frequency_number, block);
+                assert!(played.abs_diff(millihertz) <= clock.millihertz(1, block));
+            }
+        }
+    }
+
+    #[test]
+    fn frequency_number_rejects_what_cant_be_played() {
+        let highest = ChipClock::NOMINAL.millihertz(1023, 7);
+        assert!(ChipClock::NOMINAL.frequency_number(highest).is_some());
+        assert!(ChipClock::NOMINAL.frequency_number(highest * 2).is_none());
+        assert_eq!(
+            ChipClock::NOMINAL.saturating_frequency_number(highest * 2),
+            (1023, 7)
+        );
+        assert!(ChipClock::from_hz(0).frequency_number(440_000).is_none());
+    }
+
+    #[test]
+    fn calibration_round_trips() {
+        for hz in [3_579_545, 3_500_000, 3_700_000].iter() {
+            let clock = ChipClock::from_hz(*hz);
+            let measured = ChipClock::from_calibration(clock.calibration_tone_millihertz());
+            assert!(measured.hz().abs_diff(*hz) < 100);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::hl::{Initialized, Note, Opl2, Opl2Error};
+use crate::instrument::MelodyInstrument;
+use crate::ll::{ChannelId, HardwareInterface};
+
+#[derive(Debug, Copy, Clone)]
+struct Voice {
+    /// The note that is playing as `octave * 12 + semitone`, or `None` if the channel is free
+    note: Option<u8>,
+    /// When the voice was last started or stopped, in calls to the manager
+    changed: u32,
+}
+
+/// Spreads notes over the channels, like a MIDI synth does.
+///
+/// A note-on goes to the channel that has been free for the longest time, so the release of the notes
+/// before it can ring out. When all channels are playing, the oldest note is stolen.
+/// The same note played again while it sounds is restarted on its own channel.
+///
+/// In rhythm mode only the channels 0-5 are used, because the others play the drums.
+pub struct VoiceManager {
+    voices: [Voice; ChannelId::COUNT],
+    counter: u32,
+}
+
+impl VoiceManager {
+    pub const fn new() -> Self {
+        Self {
+            voices: [Voice {
+                note: None,
+                changed: 0,
+            }; ChannelId::COUNT],
+            counter: 0,
+        }
+    }
+
+    /// Starts the note with the instrument and returns the channel it plays on.
+    ///
+    /// The velocity (0-127) lowers the level of the carrier, and of the modulator too for additive instruments.
+    /// 127 plays the instrument at its own level and every 4 steps below it are 0.75 dB softer.
+    pub fn note_on<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        note: Note,
+        instrument: &MelodyInstrument,
+        velocity: u8,
+    ) -> Result<usize, Opl2Error> {
+        let key = Self::key(note);
+        let voices = &self.voices[..INIT::CHANNEL_COUNT];
+
+        let channel = match voices.iter().position(|voice| voice.note == Some(key)) {
+            Some(channel) => channel,
+            None => (0..voices.len())
+                .min_by_key(|&channel| (voices[channel].note.is_some(), voices[channel].changed))
+                .unwrap_or(0),
+        };
+
+        if self.voices[channel].note.is_some() {
+            if self.voices[channel].note != Some(key) {
+                trace!("voice on channel {} stolen", channel);
+            }
+            // Stealing or restarting a note needs a key-off first, or the envelope doesn't start again
+            opl.stop_channel(channel)?;
+        }
+
+        opl.play_note(channel, &Self::with_velocity(instrument, velocity), note)?;
+        self.mark(channel, Some(key));
+
+        Ok(channel)
+    }
+
+    /// Stops the note and returns the channel it played on, or `None` if it wasn't playing
+    pub fn note_off<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+        note: Note,
+    ) -> Result<Option<usize>, Opl2Error> {
+        let key = Self::key(note);
+        let channel = match self.voices[..INIT::CHANNEL_COUNT]
+            .iter()
+            .position(|voice| voice.note == Some(key))
+        {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
+
+        opl.stop_channel(channel)?;
+        self.mark(channel, None);
+
+        Ok(Some(channel))
+    }
+
+    /// Stops all notes that the manager started
+    pub fn all_notes_off<I: HardwareInterface, INIT: Initialized>(
+        &mut self,
+        opl: &mut Opl2<I, INIT>,
+    ) -> Result<(), Opl2Error> {
+        for channel in 0..INIT::CHANNEL_COUNT {
+            if self.voices[channel].note.is_some() {
+                opl.stop_channel(channel)?;
+                self.mark(channel, None);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The channel the note is playing on
+    pub fn channel_of(&self, note: Note) -> Option<usize> {
+        let key = Self::key(note);
+        self.voices.iter().position(|voice| voice.note == Some(key))
+    }
+
+    /// The amount of notes that are playing
+    pub fn active_voices(&self) -> usize {
+        self.voices
+            .iter()
+            .filter(|voice| voice.note.is_some())
+            .count()
+    }
+
+    fn mark(&mut self, channel: usize, note: Option<u8>) {
+        self.counter = self.counter.wrapping_add(1);
+        self.voices[channel] = Voice {
+            note,
+            changed: self.counter,
+        };
+    }
+
+    fn key(note: Note) -> u8 {
+        note.get_octave()
+            .saturating_mul(12)
+            .saturating_add(note.semitone())
+    }
+
+    fn with_velocity(instrument: &MelodyInstrument, velocity: u8) -> MelodyInstrument {
+        let attenuation = (127 - velocity.min(127)) / 4;
+        let mut bytes = instrument.to_bytes();
+
+        let soften = |level: u8| (level & 0xC0) | ((level & 0x3F) + attenuation).min(0x3F);
+        // The carrier level is byte 7, the modulator level byte 1 and bit 0 of byte 5 selects additive synthesis
+        bytes[7] = soften(bytes[7]);
+        if bytes[5] & 0x01 != 0 {
+            bytes[1] = soften(bytes[1]);
+        }
+
+        MelodyInstrument::from_bytes(bytes)
+    }
+}
+
+impl Default for VoiceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}